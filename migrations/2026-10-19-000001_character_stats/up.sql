@@ -0,0 +1,9 @@
+CREATE TABLE character_stats (
+    character_id INTEGER PRIMARY KEY REFERENCES characters(id) ON DELETE CASCADE,
+    health INTEGER NOT NULL DEFAULT 100,
+    max_health INTEGER NOT NULL DEFAULT 100,
+    damage INTEGER NOT NULL DEFAULT 10,
+    kills INTEGER NOT NULL DEFAULT 0,
+    deaths INTEGER NOT NULL DEFAULT 0,
+    modified TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
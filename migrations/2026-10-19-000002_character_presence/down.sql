@@ -0,0 +1,2 @@
+DROP TABLE character_presence;
+DROP TABLE character_sessions;
@@ -0,0 +1,15 @@
+CREATE TABLE character_sessions (
+    id SERIAL PRIMARY KEY,
+    character_id INTEGER NOT NULL REFERENCES characters(id) ON DELETE CASCADE,
+    started TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    ended TIMESTAMPTZ
+);
+
+CREATE INDEX character_sessions_character_id ON character_sessions (character_id);
+
+CREATE TABLE character_presence (
+    character_id INTEGER PRIMARY KEY REFERENCES characters(id) ON DELETE CASCADE,
+    online BOOLEAN NOT NULL DEFAULT FALSE,
+    last_seen TIMESTAMPTZ,
+    play_time BIGINT NOT NULL DEFAULT 0
+);
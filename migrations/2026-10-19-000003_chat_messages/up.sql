@@ -0,0 +1,12 @@
+CREATE TABLE chat_messages (
+    id BIGSERIAL PRIMARY KEY,
+    sender_id INTEGER NOT NULL REFERENCES characters(id) ON DELETE CASCADE,
+    sender VARCHAR NOT NULL,
+    channel VARCHAR NOT NULL,
+    recipient_id INTEGER REFERENCES characters(id) ON DELETE CASCADE,
+    party VARCHAR,
+    text TEXT NOT NULL,
+    sent TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX chat_messages_channel_id ON chat_messages (channel, id);
@@ -0,0 +1,3 @@
+DROP TABLE report_messages;
+DROP TABLE reports;
+DROP TABLE account_mutes;
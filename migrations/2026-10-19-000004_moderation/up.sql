@@ -0,0 +1,28 @@
+CREATE TABLE account_mutes (
+    character_id INTEGER PRIMARY KEY REFERENCES characters(id) ON DELETE CASCADE,
+    reason VARCHAR NOT NULL,
+    until TIMESTAMPTZ,
+    created TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE reports (
+    id SERIAL PRIMARY KEY,
+    reporter_id INTEGER NOT NULL REFERENCES characters(id) ON DELETE CASCADE,
+    target_id INTEGER NOT NULL REFERENCES characters(id) ON DELETE CASCADE,
+    reason VARCHAR NOT NULL,
+    created TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    resolved TIMESTAMPTZ,
+    resolution VARCHAR
+);
+
+CREATE INDEX reports_resolved ON reports (resolved);
+
+CREATE TABLE report_messages (
+    id BIGSERIAL PRIMARY KEY,
+    report_id INTEGER NOT NULL REFERENCES reports(id) ON DELETE CASCADE,
+    channel VARCHAR NOT NULL,
+    text TEXT NOT NULL,
+    sent TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX report_messages_report_id ON report_messages (report_id);
@@ -0,0 +1,11 @@
+CREATE TABLE account_bans (
+    id SERIAL PRIMARY KEY,
+    character_id INTEGER NOT NULL REFERENCES characters(id) ON DELETE CASCADE,
+    reason VARCHAR NOT NULL,
+    issued_by INTEGER REFERENCES characters(id) ON DELETE SET NULL,
+    created TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    until TIMESTAMPTZ,
+    revoked TIMESTAMPTZ
+);
+
+CREATE INDEX account_bans_character_id ON account_bans (character_id);
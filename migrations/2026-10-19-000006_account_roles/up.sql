@@ -0,0 +1,8 @@
+-- accounts without a row here are players. the first admin has to be
+-- granted by hand, e.g.
+--     INSERT INTO account_roles (character_id, role) VALUES (1, 'admin');
+CREATE TABLE account_roles (
+    character_id INTEGER PRIMARY KEY REFERENCES characters(id) ON DELETE CASCADE,
+    role VARCHAR NOT NULL,
+    modified TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
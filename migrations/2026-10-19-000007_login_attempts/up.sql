@@ -0,0 +1,11 @@
+CREATE TABLE login_attempts (
+    id BIGSERIAL PRIMARY KEY,
+    username VARCHAR NOT NULL,
+    address VARCHAR,
+    character_id INTEGER REFERENCES characters(id) ON DELETE SET NULL,
+    reason VARCHAR NOT NULL,
+    created TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX login_attempts_username ON login_attempts (username);
+CREATE INDEX login_attempts_address ON login_attempts (address);
@@ -0,0 +1,22 @@
+-- each login creates a session, so that tokens can be revoked before
+-- they would otherwise stop working
+CREATE TABLE login_sessions (
+    id BIGSERIAL PRIMARY KEY,
+    character_id INTEGER NOT NULL REFERENCES characters(id) ON DELETE CASCADE,
+    created TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    revoked TIMESTAMPTZ
+);
+
+CREATE INDEX login_sessions_character_id ON login_sessions (character_id);
+
+-- only a hash of each reset token is kept, like a password
+CREATE TABLE password_resets (
+    id SERIAL PRIMARY KEY,
+    character_id INTEGER NOT NULL REFERENCES characters(id) ON DELETE CASCADE,
+    token_hash VARCHAR NOT NULL,
+    created TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires TIMESTAMPTZ NOT NULL,
+    used TIMESTAMPTZ
+);
+
+CREATE INDEX password_resets_character_id ON password_resets (character_id);
@@ -0,0 +1 @@
+DROP INDEX characters_username_lower;
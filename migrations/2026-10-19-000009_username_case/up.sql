@@ -0,0 +1,13 @@
+-- usernames that only differ by case are the same name. the oldest
+-- account keeps it and the rest have their id added, so the index can
+-- be built over names that were saved before this rule existed.
+UPDATE characters SET username = username || '_' || id
+WHERE id IN (
+    SELECT id FROM (
+        SELECT id, ROW_NUMBER() OVER (PARTITION BY LOWER(username) ORDER BY id) AS n
+        FROM characters
+    ) named
+    WHERE n > 1
+);
+
+CREATE UNIQUE INDEX characters_username_lower ON characters (LOWER(username));
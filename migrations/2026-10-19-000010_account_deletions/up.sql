@@ -0,0 +1,7 @@
+-- accounts waiting to be deleted. the character (and everything that
+-- cascades from it) is purged once the grace period has passed.
+CREATE TABLE account_deletions (
+    character_id INTEGER PRIMARY KEY REFERENCES characters(id) ON DELETE CASCADE,
+    requested TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    purge_after TIMESTAMPTZ NOT NULL
+);
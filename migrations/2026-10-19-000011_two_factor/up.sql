@@ -0,0 +1,19 @@
+-- the totp secret has to be kept as it is to check codes, unlike
+-- passwords. it only protects anything once `enabled` is set.
+CREATE TABLE account_totp (
+    character_id INTEGER PRIMARY KEY REFERENCES characters(id) ON DELETE CASCADE,
+    secret VARCHAR NOT NULL,
+    created TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    enabled TIMESTAMPTZ,
+    -- the last time step a code was accepted for, so codes can't be reused
+    last_step BIGINT
+);
+
+CREATE TABLE recovery_codes (
+    id SERIAL PRIMARY KEY,
+    character_id INTEGER NOT NULL REFERENCES characters(id) ON DELETE CASCADE,
+    code_hash VARCHAR NOT NULL,
+    used TIMESTAMPTZ
+);
+
+CREATE INDEX recovery_codes_character_id ON recovery_codes (character_id);
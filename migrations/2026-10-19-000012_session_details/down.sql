@@ -0,0 +1,4 @@
+ALTER TABLE login_sessions
+    DROP COLUMN device,
+    DROP COLUMN address,
+    DROP COLUMN last_used;
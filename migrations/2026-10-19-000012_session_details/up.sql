@@ -0,0 +1,5 @@
+-- enough about each login for a player to recognize it
+ALTER TABLE login_sessions
+    ADD COLUMN device VARCHAR,
+    ADD COLUMN address VARCHAR,
+    ADD COLUMN last_used TIMESTAMPTZ NOT NULL DEFAULT NOW();
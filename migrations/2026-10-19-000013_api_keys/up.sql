@@ -0,0 +1,23 @@
+-- characters driven by tools and test bots rather than players. only
+-- these can be given api keys.
+CREATE TABLE bot_characters (
+    character_id INTEGER PRIMARY KEY REFERENCES characters(id) ON DELETE CASCADE,
+    created_by INTEGER REFERENCES characters(id) ON DELETE SET NULL,
+    created TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- only a hash of each key is kept, so a key is shown once when it's
+-- created. scopes are space separated, e.g. 'connect play'.
+CREATE TABLE api_keys (
+    id SERIAL PRIMARY KEY,
+    character_id INTEGER NOT NULL REFERENCES bot_characters(character_id) ON DELETE CASCADE,
+    name VARCHAR NOT NULL,
+    key_hash VARCHAR NOT NULL,
+    scopes VARCHAR NOT NULL,
+    created_by INTEGER REFERENCES characters(id) ON DELETE SET NULL,
+    created TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_used TIMESTAMPTZ,
+    revoked TIMESTAMPTZ
+);
+
+CREATE INDEX api_keys_character_id ON api_keys (character_id);
@@ -0,0 +1,7 @@
+DELETE FROM reports WHERE target_id IS NULL;
+
+ALTER TABLE reports
+    ALTER COLUMN target_id SET NOT NULL,
+    DROP CONSTRAINT reports_target_id_fkey,
+    ADD CONSTRAINT reports_target_id_fkey
+        FOREIGN KEY (target_id) REFERENCES characters(id) ON DELETE CASCADE;
@@ -0,0 +1,7 @@
+-- reports about a deleted account are kept for moderators, without the
+-- account they were about
+ALTER TABLE reports
+    ALTER COLUMN target_id DROP NOT NULL,
+    DROP CONSTRAINT reports_target_id_fkey,
+    ADD CONSTRAINT reports_target_id_fkey
+        FOREIGN KEY (target_id) REFERENCES characters(id) ON DELETE SET NULL;
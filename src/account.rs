@@ -0,0 +1,725 @@
+use std::time::Instant;
+
+use actix_web::{delete, get, post, web, HttpResponse, Responder, Scope};
+use chrono::{Duration, Utc};
+use validator::Validate;
+
+use crate::auth::{self, Authenticated};
+use crate::config::CONFIG;
+use crate::errors::{Error, Result};
+use crate::mailer::{Mail, Mailer};
+use crate::payloads::{
+    ChangePassword, ConfirmReset, DeleteAccount, DisableTotp, Export, RecoveryCodes, RequestReset,
+    SessionInfo, TotpCode, TotpSetup,
+};
+use crate::positions;
+use crate::queries::{self, Database};
+use crate::routes::{handler_account, kick_handler};
+use crate::throttle::{self, Key};
+use crate::totp;
+use crate::utilities;
+use crate::validation;
+
+/// Every endpoint for managing your own account, mounted under `/account`
+pub fn scope() -> Scope {
+    web::scope("/account")
+        .service(change_password)
+        .service(request_reset)
+        .service(confirm_reset)
+        .service(setup_totp)
+        .service(enable_totp)
+        .service(disable_totp)
+        .service(recovery_codes)
+        .service(sessions)
+        .service(revoke_session)
+        .service(revoke_sessions)
+        .service(export)
+        .service(delete_account)
+}
+
+// close the socket of an account if it was connected with a session
+// that has since been revoked
+async fn kick_revoked(pool: &Database, account_id: i32, reason: &str) -> Result<()> {
+    let Some(session_id) = handler_account(account_id).await.and_then(|a| a.session) else {
+        return Ok(());
+    };
+
+    let revoked = queries::fetch_session(pool, session_id)
+        .await?
+        .is_none_or(|s| s.revoked.is_some());
+
+    if revoked {
+        kick_handler(account_id, reason).await;
+    }
+    Ok(())
+}
+
+#[post("/password")]
+async fn change_password(
+    pool: web::Data<Database>,
+    account: Authenticated,
+    form: web::Json<ChangePassword>
+) -> Result<impl Responder> {
+    form.validate()?;
+    validation::password("password1", &form.password1, &account.username)?;
+
+    // the current password is needed so a stolen token can't take over
+    let character = queries::fetch_character_by_id(&pool, account.id).await?;
+    utilities::password::valid(character.password, form.current.clone())
+        .map_err(|_| Error::InvalidCredentials)?;
+
+    let password = utilities::password::hash(form.password1.clone())?;
+    queries::update_password(&pool, character.id, password).await?;
+
+    // every other login has to use the new password
+    queries::revoke_sessions(&pool, character.id, account.session).await?;
+    queries::expire_resets(&pool, character.id).await?;
+    kick_revoked(&pool, character.id, "Password was changed").await?;
+
+    Ok(HttpResponse::NoContent())
+}
+
+#[post("/password/reset")]
+async fn request_reset(
+    pool: web::Data<Database>,
+    mailer: web::Data<dyn Mailer>,
+    form: web::Json<RequestReset>
+) -> Result<impl Responder> {
+    form.validate()?;
+    let username = validation::normalize(&form.username);
+
+    // limit how much mail one account can be sent. unknown usernames are
+    // counted too, so being locked out doesn't give accounts away.
+    let key = Key::Username(username.clone());
+    let now = Instant::now();
+    let mut resets = throttle::RESETS.lock().await;
+    if let Some(wait) = resets.locked(&key, now).or_else(|| resets.fail(key, now)) {
+        return Err(Error::Locked(wait));
+    }
+    drop(resets);
+
+    // the response is the same whether or not the username exists, and
+    // is sent before looking it up, so this can't be used to find accounts
+    actix_web::rt::spawn(async move {
+        if let Err(error) = send_reset(&pool, mailer, username).await {
+            println!("RESET FAILED: {}", error);
+        }
+    });
+
+    Ok(HttpResponse::NoContent())
+}
+
+// mail a reset token to an account, if there is one with the username.
+// accounts don't have email addresses yet, so the mail is addressed to
+// the username and this only works with a mailer that knows where that
+// goes, like the file mailer used in development.
+async fn send_reset(pool: &Database, mailer: web::Data<dyn Mailer>, username: String) -> Result<()> {
+    let character = match queries::fetch_character(pool, &username).await {
+        Ok(character) => character,
+        Err(diesel::result::Error::NotFound) => return Ok(()),
+        Err(error) => return Err(error.into())
+    };
+
+    // only a hash of the secret is stored, and the id lets it be found
+    let secret = utilities::random_uuid().simple().to_string();
+    let expires = Utc::now() + Duration::from_std(CONFIG.reset_expiry).unwrap_or(Duration::hours(1));
+    let reset = queries::create_reset(pool, character.id, utilities::password::hash(&secret)?, expires).await?;
+    let token = format!("{}.{}", reset.id, secret);
+
+    let id = character.id;
+    let mail = Mail {
+        to: character.username,
+        subject: "Password reset".into(),
+        body: format!(
+            "Use this token to reset your password before {}:\n\n{}\n\n\
+             If you didn't ask for a reset, you can ignore this message.",
+            expires.to_rfc3339(),
+            token,
+        ),
+    };
+
+    let sent = web::block(move || mailer.send(&mail)).await;
+    if !matches!(sent, Ok(Ok(()))) {
+        println!("{} RESET NOT SENT", id);
+    }
+
+    Ok(())
+}
+
+#[post("/password/reset/confirm")]
+async fn confirm_reset(
+    pool: web::Data<Database>,
+    form: web::Json<ConfirmReset>
+) -> Result<impl Responder> {
+    form.validate()?;
+
+    let (id, secret) = form.token
+        .split_once('.')
+        .and_then(|(id, secret)| Some((id.parse::<i32>().ok()?, secret)))
+        .ok_or(Error::InvalidReset)?;
+
+    let reset = queries::fetch_reset(&pool, id)
+        .await?
+        .filter(|r| r.usable(Utc::now()))
+        .ok_or(Error::InvalidReset)?;
+
+    utilities::password::valid(reset.token_hash.as_str(), secret)
+        .map_err(|_| Error::InvalidReset)?;
+
+    let character = queries::fetch_character_by_id(&pool, reset.character_id).await?;
+    validation::password("password1", &form.password1, &character.username)?;
+
+    // another request may have used the token since it was fetched
+    if queries::use_reset(&pool, reset.id).await? == 0 {
+        return Err(Error::InvalidReset);
+    }
+
+    let password = utilities::password::hash(form.password1.clone())?;
+    queries::update_password(&pool, reset.character_id, password).await?;
+
+    // whoever knew the old password is logged out everywhere
+    queries::expire_resets(&pool, reset.character_id).await?;
+    queries::revoke_sessions(&pool, reset.character_id, None).await?;
+    kick_handler(reset.character_id, "Password was reset").await;
+
+    Ok(HttpResponse::NoContent())
+}
+
+#[post("/2fa/setup")]
+async fn setup_totp(
+    pool: web::Data<Database>,
+    account: Authenticated
+) -> Result<impl Responder> {
+    // two factor has to be disabled (with a code) before it's set up again
+    if totp::enabled(&pool, account.id).await?.is_some() {
+        return Err(Error::Forbidden);
+    }
+
+    let secret = totp::secret();
+    queries::set_totp_secret(&pool, account.id, &secret).await?;
+
+    Ok(web::Json(TotpSetup {
+        uri: totp::uri(&secret, &account.username),
+        secret,
+    }))
+}
+
+#[post("/2fa/enable")]
+async fn enable_totp(
+    pool: web::Data<Database>,
+    account: Authenticated,
+    form: web::Json<TotpCode>
+) -> Result<impl Responder> {
+    form.validate()?;
+
+    let setup = queries::fetch_totp(&pool, account.id)
+        .await?
+        .ok_or(diesel::result::Error::NotFound)?;
+
+    if setup.enabled.is_some() {
+        return Err(Error::Forbidden);
+    }
+
+    // a code proves the authenticator app was set up correctly
+    if !totp::check(&pool, &setup, &form.code).await? {
+        return Err(Error::InvalidCode);
+    }
+
+    queries::enable_totp(&pool, account.id).await?;
+    let codes = totp::recovery_codes(&pool, account.id).await?;
+
+    Ok(web::Json(RecoveryCodes { codes }))
+}
+
+#[post("/2fa/disable")]
+async fn disable_totp(
+    pool: web::Data<Database>,
+    account: Authenticated,
+    form: web::Json<DisableTotp>
+) -> Result<impl Responder> {
+    form.validate()?;
+
+    let character = queries::fetch_character_by_id(&pool, account.id).await?;
+    utilities::password::valid(character.password, form.password.clone())
+        .map_err(|_| Error::InvalidCredentials)?;
+
+    let setup = totp::enabled(&pool, account.id)
+        .await?
+        .ok_or(diesel::result::Error::NotFound)?;
+
+    if !totp::check(&pool, &setup, &form.code).await? {
+        return Err(Error::InvalidCode);
+    }
+
+    queries::remove_totp(&pool, account.id).await?;
+    Ok(HttpResponse::NoContent())
+}
+
+#[post("/2fa/recovery")]
+async fn recovery_codes(
+    pool: web::Data<Database>,
+    account: Authenticated,
+    form: web::Json<TotpCode>
+) -> Result<impl Responder> {
+    form.validate()?;
+
+    let setup = totp::enabled(&pool, account.id)
+        .await?
+        .ok_or(diesel::result::Error::NotFound)?;
+
+    if !totp::check(&pool, &setup, &form.code).await? {
+        return Err(Error::InvalidCode);
+    }
+
+    // the old codes stop working
+    let codes = totp::recovery_codes(&pool, account.id).await?;
+    Ok(web::Json(RecoveryCodes { codes }))
+}
+
+#[get("/sessions")]
+async fn sessions(
+    pool: web::Data<Database>,
+    account: Authenticated
+) -> Result<impl Responder> {
+    let sessions = queries::fetch_active_sessions(&pool, account.id).await?;
+
+    Ok(web::Json(sessions
+        .into_iter()
+        .map(|s| SessionInfo {
+            current: Some(s.id) == account.session,
+            id: s.id,
+            device: s.device,
+            address: s.address,
+            created: s.created,
+            last_used: s.last_used,
+        })
+        .collect::<Vec<_>>()))
+}
+
+/// Sign out one session, such as a lost device. Fails with 404 if it
+/// isn't one of yours or it's already signed out.
+#[delete("/sessions/{id}")]
+async fn revoke_session(
+    pool: web::Data<Database>,
+    account: Authenticated,
+    path: web::Path<i64>
+) -> Result<impl Responder> {
+    if queries::revoke_session(&pool, account.id, path.into_inner()).await? == 0 {
+        return Err(diesel::result::Error::NotFound.into());
+    }
+
+    kick_revoked(&pool, account.id, "Signed out").await?;
+    Ok(HttpResponse::NoContent())
+}
+
+/// Sign out every session, including the one making the request
+#[delete("/sessions")]
+async fn revoke_sessions(
+    pool: web::Data<Database>,
+    account: Authenticated
+) -> Result<impl Responder> {
+    queries::revoke_sessions(&pool, account.id, None).await?;
+    kick_handler(account.id, "Signed out").await;
+    Ok(HttpResponse::NoContent())
+}
+
+#[get("/export")]
+async fn export(
+    pool: web::Data<Database>,
+    account: Authenticated
+) -> Result<impl Responder> {
+    let character = queries::fetch_character_by_id(&pool, account.id).await?;
+
+    // positions in memory are newer than the ones in the database
+    let (x, y) = positions::current(character.id)
+        .await
+        .unwrap_or((character.x, character.y));
+
+    Ok(web::Json(Export {
+        id: character.id,
+        role: auth::fetch_role(&pool, character.id).await?,
+        created: character.created,
+        modified: character.modified,
+        x,
+        y,
+        stats: queries::fetch_stats(&pool, character.id).await?,
+        presence: queries::fetch_presence(&pool, character.id).await?,
+        chat: queries::fetch_sent_chat(&pool, character.id).await?,
+        sessions: queries::fetch_sessions(&pool, character.id).await?,
+        logins: queries::fetch_login_attempts(&pool, &character.username).await?,
+        deletion: queries::fetch_deletion(&pool, character.id).await?,
+        username: character.username,
+        exported: Utc::now(),
+    }))
+}
+
+/// Delete the account once the grace period has passed. Logging in
+/// again before then cancels the deletion.
+#[delete("")]
+async fn delete_account(
+    pool: web::Data<Database>,
+    account: Authenticated,
+    form: web::Json<DeleteAccount>
+) -> Result<impl Responder> {
+    form.validate()?;
+
+    let character = queries::fetch_character_by_id(&pool, account.id).await?;
+    utilities::password::valid(character.password, form.password.clone())
+        .map_err(|_| Error::InvalidCredentials)?;
+
+    let grace = Duration::from_std(CONFIG.deletion_grace).unwrap_or(Duration::days(30));
+    let deletion = queries::request_deletion(&pool, character.id, Utc::now() + grace).await?;
+
+    // the account is logged out everywhere, including here
+    queries::revoke_sessions(&pool, character.id, None).await?;
+    queries::expire_resets(&pool, character.id).await?;
+    kick_handler(character.id, "Account deleted").await;
+
+    Ok(web::Json(deletion))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::ResetSelect;
+    use crate::payloads::{AccountKey, Challenge, Login, VerifyLogin};
+    use crate::test_utils;
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use super::*;
+
+    async fn login<S>(app: &S, password: &str) -> (StatusCode, Option<AccountKey>)
+    where
+        S: actix_web::dev::Service<
+            actix_http::Request,
+            Response = actix_web::dev::ServiceResponse,
+            Error = actix_web::Error,
+        >,
+    {
+        let resp = test::call_service(app, test::TestRequest::post()
+            .uri("/login")
+            .set_json(Login {
+                username: "USERNAME".into(),
+                password: password.into(),
+            })
+            .to_request()).await;
+
+        let status = resp.status();
+        (status, serde_json::from_slice(&test::read_body(resp).await).ok())
+    }
+
+    // the last reset token mailed, waiting for it since mail is sent
+    // after the request returns
+    async fn mailed_token(database: &str) -> String {
+        for _ in 0..50 {
+            let contents = std::fs::read_to_string(test_utils::mail_log(database)).unwrap_or_default();
+            let token = contents
+                .lines()
+                .rev()
+                .find(|l| l.split_once('.').is_some_and(|(id, _)| id.parse::<i32>().is_ok()));
+
+            if let Some(token) = token {
+                return token.to_string();
+            }
+            actix_web::rt::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        panic!("No reset token was mailed");
+    }
+
+    #[actix_web::test]
+    async fn test_change_password() {
+        let database = "test_change_password";
+        let app = test_utils::setup(database).await;
+
+        let (_, first) = login(&app, "PASSWORD").await;
+        let (_, second) = login(&app, "PASSWORD").await;
+        let (first, second) = (first.unwrap(), second.unwrap());
+
+        // fails because the current password is wrong
+        let resp = crate::post!(app,"/account/password",ChangePassword {
+            current: "WRONGPASSWORD".into(),
+            password1: "New-Password-1".into(),
+            password2: "New-Password-1".into(),
+        },second.token);
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let resp = crate::post!(app,"/account/password",ChangePassword {
+            current: "PASSWORD".into(),
+            password1: "New-Password-1".into(),
+            password2: "New-Password-1".into(),
+        },second.token);
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        // the old password no longer works, but the new one does
+        assert_eq!(login(&app, "PASSWORD").await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(login(&app, "New-Password-1").await.0, StatusCode::OK);
+
+        // the other session was logged out, but this one wasn't
+        let form = ChangePassword {
+            current: "New-Password-1".into(),
+            password1: "New-Password-1".into(),
+            password2: "New-Password-1".into(),
+        };
+        let resp = crate::post!(app,"/account/password",form.clone(),first.token);
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = crate::post!(app,"/account/password",form,second.token);
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_reset_password() {
+        let database = "test_reset_password";
+        let app = test_utils::setup(database).await;
+        let (_, session) = login(&app, "PASSWORD").await;
+
+        // unknown usernames look the same as known ones
+        let resp = crate::post!(app,"/account/password/reset",RequestReset {
+            username: "NOBODY".into(),
+        });
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        let resp = crate::post!(app,"/account/password/reset",RequestReset {
+            username: "USERNAME".into(),
+        });
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let token = mailed_token(database).await;
+
+        // fails because the secret is wrong
+        let (id, _) = token.split_once('.').unwrap();
+        let resp = crate::post!(app,"/account/password/reset/confirm",ConfirmReset {
+            token: format!("{}.nonsense", id),
+            password1: "New-Password-1".into(),
+            password2: "New-Password-1".into(),
+        });
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let form = ConfirmReset {
+            token,
+            password1: "New-Password-1".into(),
+            password2: "New-Password-1".into(),
+        };
+        let resp = crate::post!(app,"/account/password/reset/confirm",form.clone());
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(login(&app, "New-Password-1").await.0, StatusCode::OK);
+
+        // tokens can only be used once, and existing sessions are logged out
+        let resp = crate::post!(app,"/account/password/reset/confirm",form);
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = crate::post!(app,"/account/password",ChangePassword {
+            current: "New-Password-1".into(),
+            password1: "Old-Password-1".into(),
+            password2: "Old-Password-1".into(),
+        },session.unwrap().token);
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_reset_throttled() {
+        let database = "test_reset_throttled";
+        let app = test_utils::setup(database).await;
+
+        let request = || RequestReset { username: "THROTTLED".into() };
+        for _ in 0..CONFIG.reset_requests {
+            let resp = crate::post!(app,"/account/password/reset",request());
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        }
+
+        // fails because too many resets were asked for, even though
+        // the account doesn't exist
+        let resp = crate::post!(app,"/account/password/reset",request());
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_reset_expired() {
+        let database = "test_reset_expired";
+        let app = test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+
+        let character = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+        let hash = utilities::password::hash("SECRET").unwrap();
+        let past = Utc::now() - Duration::minutes(5);
+        let reset: ResetSelect = queries::create_reset(&pool, character.id, hash, past).await.unwrap();
+
+        // fails because the token has expired
+        let resp = crate::post!(app,"/account/password/reset/confirm",ConfirmReset {
+            token: format!("{}.SECRET", reset.id),
+            password1: "New-Password-1".into(),
+            password2: "New-Password-1".into(),
+        });
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_export() {
+        let database = "test_export";
+        let app = test_utils::setup(database).await;
+        let (_, session) = login(&app, "PASSWORD").await;
+        let session = session.unwrap();
+
+        let req = test::TestRequest::get()
+            .uri("/account/export")
+            .insert_header(("Authorization", format!("Bearer {}", session.token)))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        let data: Export = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(data.id, session.id);
+        assert_eq!(data.sessions.len(), 1);
+        assert_eq!(data.logins.len(), 0);
+        assert!(data.deletion.is_none());
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_delete_account() {
+        let database = "test_delete_account";
+        let app = test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+        let (_, session) = login(&app, "PASSWORD").await;
+        let session = session.unwrap();
+
+        let delete = |password: &str| test::TestRequest::delete()
+            .uri("/account")
+            .insert_header(("Authorization", format!("Bearer {}", session.token)))
+            .set_json(DeleteAccount { password: password.into() })
+            .to_request();
+
+        // fails because the password is wrong
+        let resp = test::call_service(&app, delete("WRONGPASSWORD")).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let resp = test::call_service(&app, delete("PASSWORD")).await;
+        assert!(resp.status().is_success());
+        assert!(queries::fetch_deletion(&pool, session.id).await.unwrap().is_some());
+
+        // the token no longer works
+        let resp = test::call_service(&app, delete("PASSWORD")).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // logging in again restores the account
+        assert_eq!(login(&app, "PASSWORD").await.0, StatusCode::OK);
+        assert!(queries::fetch_deletion(&pool, session.id).await.unwrap().is_none());
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_sessions() {
+        let database = "test_sessions";
+        let app = test_utils::setup(database).await;
+
+        let resp = test::call_service(&app, test::TestRequest::post()
+            .uri("/login")
+            .insert_header(("X-Device-Name", "PHONE"))
+            .set_json(Login {
+                username: "USERNAME".into(),
+                password: "PASSWORD".into(),
+            })
+            .to_request()).await;
+        let phone: AccountKey = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        let (_, desktop) = login(&app, "PASSWORD").await;
+        let desktop = desktop.unwrap();
+
+        let request = |method: test::TestRequest, uri: &str, token: &str| method
+            .uri(uri)
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request();
+
+        let resp = test::call_service(&app, request(test::TestRequest::get(), "/account/sessions", &desktop.token)).await;
+        let listed: Vec<SessionInfo> = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(listed.len(), 2);
+
+        // the session named by the client is labelled with that name
+        let named = listed.iter().find(|s| s.device.as_deref() == Some("PHONE")).unwrap();
+        assert!(!named.current);
+        assert!(listed.iter().any(|s| s.current));
+
+        // signing out the phone stops its token from working
+        let uri = format!("/account/sessions/{}", named.id);
+        let resp = test::call_service(&app, request(test::TestRequest::delete(), &uri, &desktop.token)).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let resp = test::call_service(&app, request(test::TestRequest::get(), "/account/sessions", &phone.token)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // it can't be signed out twice
+        let resp = test::call_service(&app, request(test::TestRequest::delete(), &uri, &desktop.token)).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // signing out everywhere includes this session
+        let resp = test::call_service(&app, request(test::TestRequest::delete(), "/account/sessions", &desktop.token)).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let resp = test::call_service(&app, request(test::TestRequest::get(), "/account/sessions", &desktop.token)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_two_factor() {
+        let database = "test_two_factor";
+        let app = test_utils::setup(database).await;
+        let (_, session) = login(&app, "PASSWORD").await;
+        let token = session.unwrap().token;
+
+        let resp = crate::post!(app,"/account/2fa/setup",(),token);
+        let setup: TotpSetup = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert!(setup.uri.contains(&setup.secret));
+
+        // fails because the code is wrong
+        let resp = crate::post!(app,"/account/2fa/enable",TotpCode { code: "000000".into() },token);
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let secret = totp::decode(&setup.secret).unwrap();
+        let code = format!("{:06}", totp::code(&secret, totp::step(Utc::now())));
+        let resp = crate::post!(app,"/account/2fa/enable",TotpCode { code },token);
+        assert!(resp.status().is_success());
+        let recovery: RecoveryCodes = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(recovery.codes.len(), totp::RECOVERY_CODES);
+
+        // logging in now needs a code as well as the password
+        let resp = crate::post!(app,"/login",Login {
+            username: "USERNAME".into(),
+            password: "PASSWORD".into(),
+        });
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        let challenge: Challenge = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+
+        let verify = |code: &str| VerifyLogin {
+            challenge: challenge.challenge.clone(),
+            code: code.into(),
+        };
+
+        let resp = crate::post!(app,"/login/verify",verify("000000"));
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // recovery codes work instead, but only once
+        let code = recovery.codes[0].to_lowercase();
+        let resp = crate::post!(app,"/login/verify",verify(&code));
+        assert_eq!(resp.status(), StatusCode::OK);
+        let key: AccountKey = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+
+        let resp = crate::post!(app,"/login/verify",verify(&code));
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let resp = crate::post!(app,"/account/2fa/disable",DisableTotp {
+            password: "PASSWORD".into(),
+            code: recovery.codes[1].clone(),
+        },key.token);
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(login(&app, "PASSWORD").await.0, StatusCode::OK);
+
+        test_utils::teardown(database);
+    }
+}
@@ -0,0 +1,719 @@
+use actix_web::{delete, get, post, web, HttpResponse, Responder, Scope};
+use chrono::{Duration, Utc};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use validator::Validate;
+
+use crate::auth::{self, Admin, Authorized, Moderator};
+use crate::config::CONFIG;
+use crate::errors::{Error, Result};
+use crate::keys;
+use crate::payloads::{
+    ApiKey, Ban, Bot, Broadcast, CreateApiKey, CreateBot, Kick, Mute, NewApiKey, Player,
+    QueueDepths, ReportDetail, Reports, Resolve, SetRole, Teleport,
+};
+use crate::positions;
+use crate::protocol::Event;
+use crate::queries::{self, Database};
+use crate::routes::{
+    handler_account, kick_handler, send_event, DATABASE_QUEUE, INCOMING_QUEUE, LATENCY, LINGERING,
+    MAILBOX, OUTGOING_QUEUE, REGISTRY,
+};
+use crate::utilities;
+use crate::validation;
+
+/// Every admin endpoint, mounted under `/admin`
+pub fn scope() -> Scope {
+    web::scope("/admin")
+        .service(players)
+        .service(kick)
+        .service(teleport)
+        .service(broadcast)
+        .service(queues)
+        .service(reports)
+        .service(report_detail)
+        .service(resolve_report)
+        .service(mute)
+        .service(unmute)
+        .service(ban)
+        .service(unban)
+        .service(set_role)
+        .service(create_bot)
+        .service(bots)
+        .service(api_keys)
+        .service(create_api_key)
+        .service(revoke_api_key)
+}
+
+#[get("/players")]
+async fn players(
+    pool: web::Data<Database>,
+    _: Authorized<Admin>
+) -> Result<impl Responder> {
+
+    let connected = REGISTRY.lock().await.keys().copied().collect::<Vec<i32>>();
+    let characters = queries::fetch_characters(&pool, connected).await?;
+
+    let mut result = Vec::new();
+    for character in characters {
+        // positions in memory are newer than the ones in the database
+        let (x, y) = positions::current(character.id)
+            .await
+            .unwrap_or((character.x, character.y));
+
+        result.push(Player {
+            id: character.id,
+            username: character.username,
+            x,
+            y,
+            latency: LATENCY.lock().await.get(&character.id).map(|l| l.as_millis() as u64),
+            lingering: LINGERING.lock().await.contains_key(&character.id),
+        });
+    }
+
+    Ok(web::Json(result))
+}
+
+#[post("/players/{id}/kick")]
+async fn kick(
+    id: web::Path<i32>,
+    form: web::Json<Kick>,
+    _: Authorized<Admin>
+) -> Result<impl Responder> {
+    form.validate()?;
+
+    match kick_handler(*id, &form.reason).await {
+        true => Ok(HttpResponse::NoContent()),
+        false => Err(Error::NotConnected)
+    }
+}
+
+#[post("/characters/{id}/teleport")]
+async fn teleport(
+    pool: web::Data<Database>,
+    id: web::Path<i32>,
+    form: web::Json<Teleport>,
+    _: Authorized<Admin>
+) -> Result<impl Responder> {
+
+    let character = queries::fetch_character_by_id(&pool, *id).await?;
+
+    // offline characters are moved in the database directly, since
+    // nobody needs to see them move
+    if !REGISTRY.lock().await.contains_key(&character.id) {
+        queries::update_positions(&pool, vec![(character.id, form.x, form.y)]).await?;
+        return Ok(HttpResponse::NoContent());
+    }
+
+    let mut recipients = positions::nearby(&pool, form.x, form.y, CONFIG.event_range).await;
+    recipients.push(character.id);
+    recipients.sort();
+    recipients.dedup();
+
+    positions::record(character.id, form.x, form.y).await;
+    send_event(&recipients, Event::Teleport {
+        target: character.id,
+        x: form.x,
+        y: form.y,
+    }).await;
+
+    Ok(HttpResponse::NoContent())
+}
+
+#[post("/broadcast")]
+async fn broadcast(
+    form: web::Json<Broadcast>,
+    _: Authorized<Admin>
+) -> Result<impl Responder> {
+    form.validate()?;
+
+    let connected = REGISTRY.lock().await.keys().copied().collect::<Vec<i32>>();
+    send_event(&connected, Event::Notice {
+        text: form.text.clone(),
+        sent: Utc::now(),
+    }).await;
+
+    Ok(HttpResponse::NoContent())
+}
+
+#[get("/queues")]
+async fn queues(_: Authorized<Admin>) -> Result<impl Responder> {
+
+    Ok(web::Json(QueueDepths {
+        incoming: INCOMING_QUEUE.lock().await.len(),
+        outgoing: OUTGOING_QUEUE.lock().await.len(),
+        database: DATABASE_QUEUE.lock().await.len(),
+        events: MAILBOX.lock().await.values().map(|m| m.len()).sum(),
+        connected: REGISTRY.lock().await.len(),
+        lingering: LINGERING.lock().await.len(),
+        writes_saved: positions::writes_saved(),
+    }))
+}
+
+#[get("/reports")]
+async fn reports(
+    pool: web::Data<Database>,
+    query: web::Query<Reports>,
+    _: Authorized<Moderator>
+) -> Result<impl Responder> {
+    Ok(web::Json(queries::fetch_reports(&pool, query.resolved).await?))
+}
+
+#[get("/reports/{id}")]
+async fn report_detail(
+    pool: web::Data<Database>,
+    id: web::Path<i32>,
+    _: Authorized<Moderator>
+) -> Result<impl Responder> {
+    let (report, messages) = queries::fetch_report(&pool, *id).await?;
+    Ok(web::Json(ReportDetail { report, messages }))
+}
+
+#[post("/reports/{id}/resolve")]
+async fn resolve_report(
+    pool: web::Data<Database>,
+    id: web::Path<i32>,
+    form: web::Json<Resolve>,
+    _: Authorized<Moderator>
+) -> Result<impl Responder> {
+    form.validate()?;
+    Ok(web::Json(queries::resolve_report(&pool, *id, &form.resolution).await?))
+}
+
+#[post("/characters/{id}/mute")]
+async fn mute(
+    pool: web::Data<Database>,
+    id: web::Path<i32>,
+    form: web::Json<Mute>,
+    _: Authorized<Moderator>
+) -> Result<impl Responder> {
+    form.validate()?;
+
+    let character = queries::fetch_character_by_id(&pool, *id).await?;
+    let until = form.minutes.map(|m| Utc::now() + Duration::minutes(m));
+
+    Ok(web::Json(queries::set_mute(&pool, character.id, &form.reason, until).await?))
+}
+
+#[delete("/characters/{id}/mute")]
+async fn unmute(
+    pool: web::Data<Database>,
+    id: web::Path<i32>,
+    _: Authorized<Moderator>
+) -> Result<impl Responder> {
+    queries::remove_mute(&pool, *id).await?;
+    Ok(HttpResponse::NoContent())
+}
+
+#[post("/characters/{id}/ban")]
+async fn ban(
+    pool: web::Data<Database>,
+    id: web::Path<i32>,
+    form: web::Json<Ban>,
+    admin: Authorized<Admin>
+) -> Result<impl Responder> {
+    form.validate()?;
+
+    let character = queries::fetch_character_by_id(&pool, *id).await?;
+    let until = form.minutes.map(|m| Utc::now() + Duration::minutes(m));
+    let record = queries::create_ban(&pool, character.id, &form.reason, Some(admin.account.id), until).await?;
+
+    // a banned player doesn't get to finish their session
+    kick_handler(character.id, format!("Banned: {}", record.reason)).await;
+
+    Ok(web::Json(record))
+}
+
+#[delete("/characters/{id}/ban")]
+async fn unban(
+    pool: web::Data<Database>,
+    id: web::Path<i32>,
+    _: Authorized<Admin>
+) -> Result<impl Responder> {
+    queries::revoke_bans(&pool, *id).await?;
+    Ok(HttpResponse::NoContent())
+}
+
+#[post("/characters/{id}/role")]
+async fn set_role(
+    pool: web::Data<Database>,
+    id: web::Path<i32>,
+    form: web::Json<SetRole>,
+    admin: Authorized<Admin>
+) -> Result<impl Responder> {
+    // an admin can't lock themselves out by accident
+    if admin.account.id == *id {
+        return Err(Error::Forbidden);
+    }
+
+    let character = queries::fetch_character_by_id(&pool, *id).await?;
+    queries::set_role(&pool, character.id, form.role.name()).await?;
+
+    // routes look the role up on each request, so it applies straight away
+    Ok(web::Json(auth::fetch_role(&pool, character.id).await?))
+}
+
+/// Create a character for a tool or test bot to use with api keys. It
+/// gets a random password, so it can't be logged in to.
+#[post("/bots")]
+async fn create_bot(
+    pool: web::Data<Database>,
+    form: web::Json<CreateBot>,
+    admin: Authorized<Admin>
+) -> Result<impl Responder> {
+    let mut form = form.into_inner();
+    form.username = validation::normalize(&form.username);
+    form.validate()?;
+
+    let password = utilities::password::hash(utilities::random_uuid().simple().to_string())?;
+    let character = match queries::create_character(&pool, form.username, password).await {
+        Ok(character) => character,
+        Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            return Err(validation::taken().into())
+        }
+        Err(error) => return Err(error.into())
+    };
+
+    let bot = queries::create_bot(&pool, character.id, Some(admin.account.id)).await?;
+    Ok(web::Json(Bot {
+        id: character.id,
+        username: character.username,
+        created_by: bot.created_by,
+        created: bot.created,
+    }))
+}
+
+#[get("/bots")]
+async fn bots(
+    pool: web::Data<Database>,
+    _: Authorized<Admin>
+) -> Result<impl Responder> {
+    let bots = queries::fetch_bots(&pool).await?;
+    let characters = queries::fetch_characters(&pool, bots.iter().map(|b| b.character_id).collect()).await?;
+
+    let result = bots
+        .into_iter()
+        .filter_map(|bot| {
+            let character = characters.iter().find(|c| c.id == bot.character_id)?;
+            Some(Bot {
+                id: character.id,
+                username: character.username.clone(),
+                created_by: bot.created_by,
+                created: bot.created,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Ok(web::Json(result))
+}
+
+#[get("/bots/{id}/keys")]
+async fn api_keys(
+    pool: web::Data<Database>,
+    id: web::Path<i32>,
+    _: Authorized<Admin>
+) -> Result<impl Responder> {
+    let bot = queries::fetch_bot(&pool, *id)
+        .await?
+        .ok_or(diesel::result::Error::NotFound)?;
+
+    let keys = queries::fetch_api_keys(&pool, bot.character_id).await?;
+    Ok(web::Json(keys.into_iter().map(ApiKey::from).collect::<Vec<_>>()))
+}
+
+/// Issue a key for a bot. The response is the only time the key is
+/// shown.
+#[post("/bots/{id}/keys")]
+async fn create_api_key(
+    pool: web::Data<Database>,
+    id: web::Path<i32>,
+    form: web::Json<CreateApiKey>,
+    admin: Authorized<Admin>
+) -> Result<impl Responder> {
+    form.validate()?;
+
+    // only bots can have keys, so players can't be impersonated
+    let bot = queries::fetch_bot(&pool, *id)
+        .await?
+        .ok_or(diesel::result::Error::NotFound)?;
+
+    let (record, key) = keys::issue(
+        &pool,
+        bot.character_id,
+        &form.name,
+        &form.scopes,
+        Some(admin.account.id),
+    ).await?;
+
+    Ok(web::Json(NewApiKey {
+        info: record.into(),
+        key,
+    }))
+}
+
+#[delete("/keys/{id}")]
+async fn revoke_api_key(
+    pool: web::Data<Database>,
+    id: web::Path<i32>,
+    _: Authorized<Admin>
+) -> Result<impl Responder> {
+    let key = queries::fetch_api_key(&pool, *id)
+        .await?
+        .ok_or(diesel::result::Error::NotFound)?;
+
+    if queries::revoke_api_key(&pool, key.id).await? == 0 {
+        return Err(diesel::result::Error::NotFound.into());
+    }
+
+    // a bot connected with the key is dropped straight away
+    let connected = handler_account(key.character_id)
+        .await
+        .and_then(|a| a.key)
+        .is_some_and(|k| k.id == key.id);
+
+    if connected {
+        kick_handler(key.character_id, "Api key revoked").await;
+    }
+
+    Ok(HttpResponse::NoContent())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::Role;
+    use crate::payloads::{AccountInfo, Report};
+    use crate::routes::{register_handler, unregister_handler};
+    use crate::test_utils;
+    use actix_web::http::header::AUTHORIZATION;
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use super::*;
+
+    async fn bearer(pool: &Database, id: i32, role: Role) -> (actix_web::http::header::HeaderName, String) {
+        (AUTHORIZATION, format!("Bearer {}", test_utils::token(pool, id, role).await))
+    }
+
+    // give a character a role, since routes check it against the database
+    async fn grant(pool: &Database, id: i32, role: Role) -> (actix_web::http::header::HeaderName, String) {
+        queries::set_role(pool, id, role.name()).await.unwrap();
+        bearer(pool, id, role).await
+    }
+
+    #[actix_web::test]
+    async fn test_endpoint_players1() {
+        let database = "test_endpoint_players1";
+        let app = test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+        let admin = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+
+        // a connected player that has moved and answered a ping
+        let player = queries::create_character(&pool, "PLAYER", "PASSWORD").await.unwrap();
+        register_handler(AccountInfo { id: player.id, username: player.username.clone(), role: Role::Player, session: None, key: None }).await;
+        positions::record(player.id, 4.0, 5.0).await;
+        LATENCY.lock().await.insert(player.id, std::time::Duration::from_millis(30));
+
+        let req = test::TestRequest::get()
+            .uri("/admin/players")
+            .insert_header(grant(&pool, admin.id, Role::Admin).await)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        let connected: Vec<Player> = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        unregister_handler(player.id).await;
+        positions::POSITIONS.lock().await.remove(&player.id);
+
+        let listed = connected.iter().find(|p| p.id == player.id).expect("player is listed");
+        assert_eq!(listed.username, "PLAYER");
+        assert_eq!((listed.x, listed.y), (4.0, 5.0));
+        assert_eq!(listed.latency, Some(30));
+        assert!(!listed.lingering);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_endpoint_players2() {
+        let database = "test_endpoint_players2";
+        let app = test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+        let moderator = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+
+        // fails because there's no token
+        let resp = crate::get!(app,"/admin/players",());
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // fails because moderators can't manage players
+        let req = test::TestRequest::get()
+            .uri("/admin/players")
+            .insert_header(grant(&pool, moderator.id, Role::Moderator).await)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // fails because the token isn't from a login
+        let token = utilities::token::encode(&AccountInfo {
+            id: moderator.id,
+            username: moderator.username,
+            role: Role::Admin,
+            session: None,
+            key: None,
+        }).unwrap();
+        let req = test::TestRequest::get()
+            .uri("/admin/players")
+            .insert_header((AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_endpoint_kick() {
+        let database = "test_endpoint_kick";
+        let app = test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+        let admin = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+
+        // fails because the character isn't connected
+        let req = test::TestRequest::post()
+            .uri("/admin/players/0/kick")
+            .insert_header(grant(&pool, admin.id, Role::Admin).await)
+            .set_json(Kick { reason: "BEHAVE".into() })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // fails because the reason is too many bytes for a close frame
+        let req = test::TestRequest::post()
+            .uri("/admin/players/0/kick")
+            .insert_header(grant(&pool, admin.id, Role::Admin).await)
+            .set_json(Kick { reason: "é".repeat(100) })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_endpoint_mute() {
+        let database = "test_endpoint_mute";
+        let app = test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+        let moderator = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+        let header = grant(&pool, moderator.id, Role::Moderator).await;
+        let target = queries::create_character(&pool, "MUTED", "PASSWORD").await.unwrap();
+        let uri = format!("/admin/characters/{}/mute", target.id);
+
+        // fails because the mute would end past any date
+        let req = test::TestRequest::post()
+            .uri(&uri)
+            .insert_header(header.clone())
+            .set_json(Mute { reason: "SPAM".into(), minutes: Some(i64::MAX) })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let req = test::TestRequest::post()
+            .uri(&uri)
+            .insert_header(header)
+            .set_json(Mute { reason: "SPAM".into(), minutes: Some(60) })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_endpoint_ban() {
+        let database = "test_endpoint_ban";
+        let app = test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+        let admin = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+        let header = grant(&pool, admin.id, Role::Admin).await;
+        let target = queries::create_character(&pool, "BANNED", "PASSWORD").await.unwrap();
+        let uri = format!("/admin/characters/{}/ban", target.id);
+
+        // fails because the ban would end past any date
+        let req = test::TestRequest::post()
+            .uri(&uri)
+            .insert_header(header.clone())
+            .set_json(Ban { reason: "CHEATING".into(), minutes: Some(i64::MAX) })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let req = test::TestRequest::post()
+            .uri(&uri)
+            .insert_header(header)
+            .set_json(Ban { reason: "CHEATING".into(), minutes: Some(60) })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_endpoint_reports() {
+        let database = "test_endpoint_reports";
+        let app = test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+        let moderator = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+
+        // moderators can review reports
+        let req = test::TestRequest::get()
+            .uri("/admin/reports")
+            .insert_header(grant(&pool, moderator.id, Role::Moderator).await)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        // but players can't
+        let req = test::TestRequest::get()
+            .uri("/admin/reports")
+            .insert_header(grant(&pool, moderator.id, Role::Player).await)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_endpoint_set_role() {
+        let database = "test_endpoint_set_role";
+        let app = test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+
+        let character = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+        let admin = queries::create_character(&pool, "ADMIN", "PASSWORD").await.unwrap();
+        let admin = grant(&pool, admin.id, Role::Admin).await;
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/admin/characters/{}/role", character.id))
+            .insert_header(admin.clone())
+            .set_json(SetRole { role: Role::Moderator })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        assert_eq!(auth::fetch_role(&pool, character.id).await.unwrap(), Role::Moderator);
+
+        // fails because admins can't change their own role
+        let req = test::TestRequest::post()
+            .uri(&format!("/admin/characters/{}/role", character.id))
+            .insert_header(grant(&pool, character.id, Role::Admin).await)
+            .set_json(SetRole { role: Role::Player })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_endpoint_set_role2() {
+        let database = "test_endpoint_set_role2";
+        let app = test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+
+        let moderator = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+        let admin = queries::create_character(&pool, "ADMIN", "PASSWORD").await.unwrap();
+        let admin = grant(&pool, admin.id, Role::Admin).await;
+
+        let token = grant(&pool, moderator.id, Role::Moderator).await;
+        let review = || test::TestRequest::get()
+            .uri("/admin/reports")
+            .insert_header(token.clone())
+            .to_request();
+        let resp = test::call_service(&app, review()).await;
+        assert!(resp.status().is_success());
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/admin/characters/{}/role", moderator.id))
+            .insert_header(admin)
+            .set_json(SetRole { role: Role::Player })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        // fails because the moderator was demoted, even though their
+        // token still says otherwise
+        let resp = test::call_service(&app, review()).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_api_keys() {
+        let database = "test_api_keys";
+        let app = test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+        let admin = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+        let authorization = grant(&pool, admin.id, Role::Admin).await;
+
+        let req = test::TestRequest::post()
+            .uri("/admin/bots")
+            .insert_header(authorization.clone())
+            .set_json(CreateBot { username: "TESTBOT".into() })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let bot: Bot = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(bot.created_by, Some(admin.id));
+
+        let issue = |id: i32, scopes: Vec<keys::KeyScope>| test::TestRequest::post()
+            .uri(&format!("/admin/bots/{}/keys", id))
+            .insert_header(authorization.clone())
+            .set_json(CreateApiKey { name: "TOOL".into(), scopes })
+            .to_request();
+
+        // fails because players can't be given keys
+        let resp = test::call_service(&app, issue(admin.id, vec![keys::KeyScope::Report])).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let resp = test::call_service(&app, issue(bot.id, vec![keys::KeyScope::Report])).await;
+        let reporter: NewApiKey = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        let resp = test::call_service(&app, issue(bot.id, vec![keys::KeyScope::Connect])).await;
+        let watcher: NewApiKey = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+
+        let report = |key: &str| test::TestRequest::post()
+            .uri("/report")
+            .insert_header((AUTHORIZATION, format!("Bearer {}", key)))
+            .set_json(Report { target: admin.id, reason: "SPAM".into() })
+            .to_request();
+
+        // keys can only do what their scopes allow
+        let resp = test::call_service(&app, report(&reporter.key)).await;
+        assert!(resp.status().is_success());
+        let resp = test::call_service(&app, report(&watcher.key)).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // keys are listed without their secret
+        let req = test::TestRequest::get()
+            .uri(&format!("/admin/bots/{}/keys", bot.id))
+            .insert_header(authorization.clone())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body = test::read_body(resp).await;
+        let listed: Vec<ApiKey> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(listed.len(), 2);
+        assert!(listed[0].last_used.is_some());
+        assert!(!String::from_utf8_lossy(&body).contains(&reporter.key));
+
+        // revoked keys stop working
+        let req = test::TestRequest::delete()
+            .uri(&format!("/admin/keys/{}", reporter.info.id))
+            .insert_header(authorization.clone())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let resp = test::call_service(&app, report(&reporter.key)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        test_utils::teardown(database);
+    }
+}
@@ -0,0 +1,368 @@
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use futures_util::future::LocalBoxFuture;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::errors::{Error, Result};
+use crate::keys::{self, KeyScope};
+use crate::moderation;
+use crate::payloads::AccountInfo;
+use crate::queries::{self, Database};
+use crate::utilities;
+
+// ------------------------------------------------
+// Role
+
+/// What an account is allowed to do. Each role can do everything the
+/// roles before it can.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[default]
+    Player,
+    /// Can review reports and mute players
+    Moderator,
+    /// Can manage players and the live server
+    Admin,
+}
+
+impl Role {
+    /// The name the role is stored under
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Player => "player",
+            Self::Moderator => "moderator",
+            Self::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = ();
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "player" => Ok(Self::Player),
+            "moderator" => Ok(Self::Moderator),
+            "admin" => Ok(Self::Admin),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Look up the role of an account, treating unknown roles as players
+pub async fn fetch_role(database: &Database, character_id: i32) -> Result<Role> {
+    Ok(queries::fetch_role(database, character_id)
+        .await?
+        .and_then(|r| r.parse().ok())
+        .unwrap_or_default())
+}
+// ------------------------------------------------
+
+// ------------------------------------------------
+// Authenticated
+
+// the token in an `Authorization: Bearer <token>` header
+fn bearer_token(req: &HttpRequest) -> Result<String> {
+    req.headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|v| v.trim().to_string())
+        .ok_or(Error::Unauthorized)
+}
+
+// decode the token in an `Authorization: Bearer <token>` header
+fn bearer<T: DeserializeOwned>(req: &HttpRequest) -> Result<T> {
+    utilities::token::decode(bearer_token(req)?).map_err(|_| Error::Unauthorized)
+}
+
+/// Fail if the login session a token belongs to has been revoked, e.g.
+/// by a password change. Every login has a session, so only api keys
+/// (which are checked on their own) can be without one.
+pub async fn check_session(database: &Database, account: &AccountInfo) -> Result<()> {
+    let Some(session_id) = account.session else {
+        return match account.key {
+            Some(_) => Ok(()),
+            None => Err(Error::Unauthorized),
+        };
+    };
+
+    match queries::fetch_session(database, session_id).await? {
+        Some(session) if session.character_id == account.id && session.revoked.is_none() => {
+            queries::touch_session(database, session_id).await?;
+            Ok(())
+        }
+        _ => Err(Error::Unauthorized),
+    }
+}
+
+// refuse accounts that were banned or logged out after their token was
+// issued. nobody is let in if the app has no database to check against.
+async fn check_account(database: Option<web::Data<Database>>, account: &AccountInfo) -> Result<()> {
+    let database = database.ok_or(Error::Unauthorized)?;
+    check_session(&database, account).await?;
+    moderation::check_ban(&database, account.id).await
+}
+
+/// An extractor for routes that need a login, read from the
+/// `Authorization: Bearer <token>` header. Fails with 401 if there's no
+/// valid token (or its session was revoked), and 403 if the account has
+/// been banned since.
+pub struct Authenticated(pub AccountInfo);
+
+impl Deref for Authenticated {
+    type Target = AccountInfo;
+
+    fn deref(&self) -> &AccountInfo {
+        &self.0
+    }
+}
+
+impl FromRequest for Authenticated {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let account = bearer::<AccountInfo>(req);
+        let database = req.app_data::<web::Data<Database>>().cloned();
+
+        Box::pin(async move {
+            let account = account?;
+            check_account(database, &account).await?;
+            Ok(Self(account))
+        })
+    }
+}
+// ------------------------------------------------
+
+// ------------------------------------------------
+// Authorized
+
+/// The least role a route needs, used as the parameter of `Authorized`
+pub trait Requirement {
+    const ROLE: Role;
+}
+
+/// Requires a moderator or admin
+pub struct Moderator;
+
+/// Requires an admin
+pub struct Admin;
+
+impl Requirement for Moderator {
+    const ROLE: Role = Role::Moderator;
+}
+
+impl Requirement for Admin {
+    const ROLE: Role = Role::Admin;
+}
+
+/// Like `Authenticated`, but for routes that need a particular role.
+/// Also fails with 403 if the account's role isn't high enough. The
+/// role is looked up again rather than trusted from the token, so a
+/// change of role applies straight away.
+pub struct Authorized<R: Requirement> {
+    pub account: AccountInfo,
+    requirement: PhantomData<R>,
+}
+
+impl<R: Requirement> Authorized<R> {
+    fn check(account: AccountInfo) -> Result<Self> {
+        if account.role < R::ROLE {
+            return Err(Error::Forbidden);
+        }
+
+        Ok(Self {
+            account,
+            requirement: PhantomData,
+        })
+    }
+}
+
+impl<R: Requirement + 'static> FromRequest for Authorized<R> {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let account = bearer::<AccountInfo>(req);
+        let database = req.app_data::<web::Data<Database>>().cloned();
+
+        Box::pin(async move {
+            let mut account = account?;
+            check_account(database.clone(), &account).await?;
+
+            let database = database.ok_or(Error::Unauthorized)?;
+            account.role = fetch_role(&database, account.id).await?;
+
+            Self::check(account)
+        })
+    }
+}
+// ------------------------------------------------
+
+// ------------------------------------------------
+// Permitted
+
+/// The scope an api key needs for a route, used as the parameter of
+/// `Permitted`
+pub trait Grant {
+    const SCOPE: KeyScope;
+}
+
+/// Requires the `report` scope from api keys
+pub struct Reporting;
+
+impl Grant for Reporting {
+    const SCOPE: KeyScope = KeyScope::Report;
+}
+
+/// Requires the `connect` scope from api keys
+pub struct Connecting;
+
+impl Grant for Connecting {
+    const SCOPE: KeyScope = KeyScope::Connect;
+}
+
+/// Like `Authenticated`, but for routes that bots can use too. Also
+/// accepts an api key in place of the token, failing with 403 if the
+/// key doesn't have the scope.
+pub struct Permitted<G: Grant> {
+    pub account: AccountInfo,
+    grant: PhantomData<G>,
+}
+
+impl<G: Grant> Deref for Permitted<G> {
+    type Target = AccountInfo;
+
+    fn deref(&self) -> &AccountInfo {
+        &self.account
+    }
+}
+
+impl<G: Grant + 'static> FromRequest for Permitted<G> {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let token = bearer_token(req);
+        let database = req.app_data::<web::Data<Database>>().cloned();
+
+        Box::pin(async move {
+            let token = token?;
+
+            let account = match keys::is_key(&token) {
+                true => {
+                    let database = database.ok_or(Error::Unauthorized)?;
+                    let account = keys::authenticate(&database, &token).await?;
+                    if !account.allows(G::SCOPE) {
+                        return Err(Error::Forbidden);
+                    }
+                    moderation::check_ban(&database, account.id).await?;
+                    account
+                }
+                false => {
+                    let account: AccountInfo = utilities::token::decode(token)
+                        .map_err(|_| Error::Unauthorized)?;
+                    check_account(database, &account).await?;
+                    account
+                }
+            };
+
+            Ok(Self {
+                account,
+                grant: PhantomData,
+            })
+        })
+    }
+}
+// ------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    // a request from a character with the role, logged in against the
+    // test database
+    async fn request(pool: &Database, role: Role) -> HttpRequest {
+        let character = queries::fetch_character(pool, "USERNAME").await.unwrap();
+        queries::set_role(pool, character.id, role.name()).await.unwrap();
+        let token = crate::test_utils::token(pool, character.id, role).await;
+
+        TestRequest::default()
+            .app_data(web::Data::new(pool.clone()))
+            .insert_header((AUTHORIZATION, format!("Bearer {}", token)))
+            .to_http_request()
+    }
+
+    #[actix_web::test]
+    async fn test_role_order() {
+        assert!(Role::Player < Role::Moderator);
+        assert!(Role::Moderator < Role::Admin);
+        assert_eq!("Admin".parse(), Ok(Role::Admin));
+    }
+
+    #[actix_web::test]
+    async fn test_authorized1() {
+        let database = "test_authorized1";
+        let _ = crate::test_utils::setup(database).await;
+        let pool = crate::test_utils::pool(database).await;
+
+        // higher roles meet lower requirements
+        assert!(Authorized::<Moderator>::extract(&request(&pool, Role::Moderator).await).await.is_ok());
+        assert!(Authorized::<Moderator>::extract(&request(&pool, Role::Admin).await).await.is_ok());
+        assert!(Authorized::<Admin>::extract(&request(&pool, Role::Admin).await).await.is_ok());
+
+        crate::test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_authorized2() {
+        let database = "test_authorized2";
+        let _ = crate::test_utils::setup(database).await;
+        let pool = crate::test_utils::pool(database).await;
+
+        // fails because the role isn't high enough
+        let result = Authorized::<Admin>::extract(&request(&pool, Role::Moderator).await).await;
+        assert!(matches!(result, Err(Error::Forbidden)));
+
+        let result = Authorized::<Moderator>::extract(&request(&pool, Role::Player).await).await;
+        assert!(matches!(result, Err(Error::Forbidden)));
+
+        crate::test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_authorized3() {
+        // fails because there's no valid token
+        let result = Authorized::<Admin>::extract(&TestRequest::default().to_http_request()).await;
+        assert!(matches!(result, Err(Error::Unauthorized)));
+
+        let req = TestRequest::default()
+            .insert_header((AUTHORIZATION, "Bearer nonsense"))
+            .to_http_request();
+        assert!(matches!(Authorized::<Admin>::extract(&req).await, Err(Error::Unauthorized)));
+    }
+
+    #[actix_web::test]
+    async fn test_authenticated() {
+        // fails because there's no database to check the session against
+        let token = utilities::token::encode(&AccountInfo {
+            id: 1,
+            username: "USERNAME".into(),
+            role: Role::Player,
+            session: None,
+            key: None,
+        }).unwrap();
+        let req = TestRequest::default()
+            .insert_header((AUTHORIZATION, format!("Bearer {}", token)))
+            .to_http_request();
+        assert!(matches!(Authenticated::extract(&req).await, Err(Error::Unauthorized)));
+    }
+}
+
@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use futures_util::lock::Mutex;
+use once_cell::sync::Lazy;
+use tinker_records::models::CharacterSelect;
+
+use crate::config::CONFIG;
+use crate::models::ChatInsert;
+use crate::moderation;
+use crate::positions;
+use crate::protocol::{Channel, Control, ErrorCode, Event, Request};
+use crate::queries::{self, Database};
+use crate::routes::{send_event, REGISTRY};
+
+// the party each character is in, by name
+pub static PARTIES: Lazy<Mutex<HashMap<i32,String>>> = Lazy::new(|| { Default::default() });
+
+// the party invites each character has been sent
+pub static INVITES: Lazy<Mutex<HashMap<i32,Vec<Invite>>>> = Lazy::new(|| { Default::default() });
+
+/// An invite to a party, from one of its members
+#[derive(Clone, Debug, PartialEq)]
+pub struct Invite {
+    pub party: String,
+    pub inviter: i32,
+}
+
+// build the error frame sent back to a client for a bad request
+fn error<T: ToString>(code: ErrorCode, message: T) -> Control {
+    Control::Error {
+        code,
+        message: message.to_string(),
+        id: None,
+    }
+}
+
+/// Trim a chat message or party name, and check that it isn't empty
+/// or longer than the configured limit.
+pub fn validate(text: &str) -> Result<String, Control> {
+    let text = text.trim();
+    let length = text.chars().count();
+
+    if length == 0 || length > CONFIG.chat_length {
+        return Err(error(
+            ErrorCode::InvalidLength,
+            format!("Text must be between 1 and {} characters", CONFIG.chat_length),
+        ));
+    }
+
+    Ok(text.to_string())
+}
+
+/// Put a character in a party, leaving any party they were in
+pub async fn join_party(character_id: i32, name: String) {
+    PARTIES.lock().await.insert(character_id, name);
+}
+
+/// Take a character out of their party, if they're in one
+pub async fn leave_party(character_id: i32) {
+    PARTIES.lock().await.remove(&character_id);
+}
+
+/// Invite a character to a party, on behalf of one of its members
+pub async fn invite(character_id: i32, inviter: i32, name: String) {
+    let mut invites = INVITES.lock().await;
+    let pending = invites.entry(character_id).or_default();
+    pending.retain(|i| i.party != name);
+    pending.push(Invite { party: name, inviter });
+}
+
+/// Forget every invite a character was sent
+pub async fn forget_invites(character_id: i32) {
+    INVITES.lock().await.remove(&character_id);
+}
+
+/// Check if a character can join a party, using up their invite to it
+/// if they need one. Anyone can start a party under an unused name, but
+/// joining one with members takes an invite from a current member.
+pub async fn admit(character_id: i32, name: &str) -> bool {
+    let members = party_members(name).await;
+    if members.is_empty() {
+        return true;
+    }
+
+    let mut invites = INVITES.lock().await;
+    let Some(pending) = invites.get_mut(&character_id) else {
+        return false;
+    };
+
+    match pending.iter().position(|i| i.party == name && members.contains(&i.inviter)) {
+        Some(index) => {
+            pending.remove(index);
+            true
+        }
+        None => false,
+    }
+}
+
+/// The party a character is in
+pub async fn party(character_id: i32) -> Option<String> {
+    PARTIES.lock().await.get(&character_id).cloned()
+}
+
+/// Every character in a party
+pub async fn party_members(name: &str) -> Vec<i32> {
+    PARTIES.lock().await
+        .iter()
+        .filter(|(_, party)| *party == name)
+        .map(|(id, _)| *id)
+        .collect()
+}
+
+/// Handle a request from a client, returning the error frame to send
+/// back if it can't be handled.
+pub async fn handle(
+    database: &Database,
+    sender: &CharacterSelect,
+    request: Request,
+) -> Result<(), Control> {
+    match request {
+        Request::Chat { channel, text } => send(database, sender, channel, text).await,
+        Request::JoinParty { name } => {
+            let name = CONFIG.chat_filter.apply(&validate(&name)?);
+            if !admit(sender.id, &name).await {
+                return Err(error(
+                    ErrorCode::NotInvited,
+                    format!("{} can only be joined with an invite", name),
+                ));
+            }
+            join_party(sender.id, name).await;
+            Ok(())
+        }
+        Request::InviteParty { to } => {
+            let Some(name) = party(sender.id).await else {
+                return Err(error(ErrorCode::NoParty, "You are not in a party"));
+            };
+            let Some(recipient) = online(database, &to).await else {
+                return Err(error(ErrorCode::UnknownRecipient, format!("{} is not online", to)));
+            };
+
+            invite(recipient.id, sender.id, name.clone()).await;
+            send_event(&[recipient.id], Event::PartyInvite {
+                sender: sender.id,
+                name: sender.username.clone(),
+                party: name,
+            }).await;
+            Ok(())
+        }
+        Request::LeaveParty => {
+            leave_party(sender.id).await;
+            Ok(())
+        }
+    }
+}
+
+// a connected character, by name
+async fn online(database: &Database, name: &str) -> Option<CharacterSelect> {
+    let character = queries::fetch_character(database, name).await.ok()?;
+    let connected = REGISTRY.lock().await.contains_key(&character.id);
+    connected.then_some(character)
+}
+
+// deliver a chat message to everyone on the channel, and save it if
+// chat history is enabled
+async fn send(
+    database: &Database,
+    sender: &CharacterSelect,
+    channel: Channel,
+    text: String,
+) -> Result<(), Control> {
+    if let Some(until) = moderation::muted(database, sender.id).await {
+        let message = match until {
+            Some(until) => format!("You are muted until {}", until.to_rfc3339()),
+            None => "You are muted".to_string(),
+        };
+        return Err(error(ErrorCode::Muted, message));
+    }
+
+    let text = validate(&text)?;
+
+    let mut recipient_id = None;
+    let mut party_name = None;
+
+    let mut recipients = match &channel {
+        Channel::Local => {
+            let (x, y) = positions::current(sender.id)
+                .await
+                .unwrap_or((sender.x, sender.y));
+            positions::nearby(database, x, y, CONFIG.chat_range).await
+        }
+        Channel::Global => REGISTRY.lock().await.keys().copied().collect(),
+        Channel::Whisper { to } => match online(database, to).await {
+            Some(r) => {
+                recipient_id = Some(r.id);
+                vec![r.id]
+            }
+            None => {
+                return Err(error(
+                    ErrorCode::UnknownRecipient,
+                    format!("{} is not online", to),
+                ))
+            }
+        },
+        Channel::Party => match party(sender.id).await {
+            Some(name) => {
+                let members = party_members(&name).await;
+                party_name = Some(name);
+                members
+            }
+            None => return Err(error(ErrorCode::NoParty, "You are not in a party")),
+        },
+    };
+
+    // the sender always sees their own message
+    recipients.push(sender.id);
+    recipients.sort();
+    recipients.dedup();
+
+    let sent = Utc::now();
+
+    // reports are about what the player wrote, not what others saw
+    moderation::remember(sender.id, channel.name(), &text, sent).await;
+    let text = CONFIG.chat_filter.apply(&text);
+
+    if CONFIG.chat_history {
+        let _ = queries::insert_chat(database, ChatInsert {
+            sender_id: sender.id,
+            sender: sender.username.clone(),
+            channel: channel.name().to_string(),
+            recipient_id,
+            party: party_name,
+            text: text.clone(),
+            sent,
+        }).await;
+    }
+
+    send_event(&recipients, Event::Chat {
+        sender: sender.id,
+        name: sender.username.clone(),
+        channel,
+        text,
+        sent,
+    }).await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Role;
+    use crate::payloads::AccountInfo;
+    use crate::routes::{read_events, register_handler, unregister_handler};
+    use crate::test_utils;
+
+    fn code(result: Result<(), Control>) -> Option<ErrorCode> {
+        match result {
+            Err(Control::Error { code, .. }) => Some(code),
+            _ => None,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_validate1() {
+        assert_eq!(validate("  hello "), Ok("hello".to_string()));
+    }
+
+    #[actix_web::test]
+    async fn test_validate2() {
+        // empty and overlong messages are rejected
+        let long = "a".repeat(CONFIG.chat_length + 1);
+        assert!(validate("   ").is_err());
+        assert!(validate(&long).is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_party_members() {
+        join_party(-10, "PARTY".into()).await;
+        join_party(-11, "PARTY".into()).await;
+        join_party(-12, "OTHER".into()).await;
+
+        let mut members = party_members("PARTY").await;
+        members.sort();
+        assert_eq!(members, vec![-11, -10]);
+
+        // joining another party leaves the first
+        join_party(-11, "OTHER".into()).await;
+        assert_eq!(party_members("PARTY").await, vec![-10]);
+
+        leave_party(-10).await;
+        leave_party(-11).await;
+        leave_party(-12).await;
+        assert_eq!(party(-10).await, None);
+    }
+
+    #[actix_web::test]
+    async fn test_handle_party() {
+        let database = "test_handle_party";
+        test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+
+        let leader = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+        let other = queries::create_character(&pool, "TEST", "PASSWORD").await.unwrap();
+        register_handler(AccountInfo { id: leader.id, username: leader.username.clone(), role: Role::Player, session: None, key: None }).await;
+        register_handler(AccountInfo { id: other.id, username: other.username.clone(), role: Role::Player, session: None, key: None }).await;
+
+        // anyone can start a party
+        let join = || Request::JoinParty { name: "PARTY".into() };
+        assert!(handle(&pool, &leader, join()).await.is_ok());
+
+        // but can't join one with members without an invite
+        let result = handle(&pool, &other, join()).await;
+        assert_eq!(code(result), Some(ErrorCode::NotInvited));
+
+        let invite = Request::InviteParty { to: "TEST".into() };
+        assert!(handle(&pool, &leader, invite).await.is_ok());
+
+        let events = read_events(other.id).await;
+        assert!(matches!(&events[..], [Event::PartyInvite { party, .. }] if party == "PARTY"));
+
+        assert!(handle(&pool, &other, join()).await.is_ok());
+        assert_eq!(party(other.id).await, Some("PARTY".into()));
+
+        // the invite is used up by joining
+        leave_party(other.id).await;
+        let result = handle(&pool, &other, join()).await;
+        assert_eq!(code(result), Some(ErrorCode::NotInvited));
+
+        unregister_handler(leader.id).await;
+        unregister_handler(other.id).await;
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_handle_chat() {
+        let database = "test_handle_chat";
+        test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+
+        let sender = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+        let other = queries::create_character(&pool, "TEST", "PASSWORD").await.unwrap();
+
+        // whispers only go to connected characters
+        let request = Request::Chat {
+            channel: Channel::Whisper { to: "TEST".into() },
+            text: "hello".into(),
+        };
+        let result = handle(&pool, &sender, request.clone()).await;
+        assert_eq!(code(result), Some(ErrorCode::UnknownRecipient));
+
+        // party chat needs a party
+        let result = handle(&pool, &sender, Request::Chat {
+            channel: Channel::Party,
+            text: "hello".into(),
+        }).await;
+        assert_eq!(code(result), Some(ErrorCode::NoParty));
+
+        register_handler(AccountInfo { id: sender.id, username: sender.username.clone(), role: Role::Player, session: None, key: None }).await;
+        register_handler(AccountInfo { id: other.id, username: other.username.clone(), role: Role::Player, session: None, key: None }).await;
+
+        assert!(handle(&pool, &sender, request).await.is_ok());
+
+        let events = read_events(other.id).await;
+        assert!(matches!(&events[..], [Event::Chat { text, .. }] if text == "hello"));
+        assert_eq!(read_events(sender.id).await.len(), 1);
+
+        // muted characters can't chat at all
+        queries::set_mute(&pool, sender.id, "SPAM", None).await.unwrap();
+        let result = handle(&pool, &sender, Request::Chat {
+            channel: Channel::Global,
+            text: "hello".into(),
+        }).await;
+        assert_eq!(code(result), Some(ErrorCode::Muted));
+
+        unregister_handler(sender.id).await;
+        unregister_handler(other.id).await;
+        test_utils::teardown(database);
+    }
+}
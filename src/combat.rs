@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use futures_util::lock::Mutex;
+use once_cell::sync::Lazy;
+use tinker_records::messages::{Message, Value};
+
+use crate::config::CONFIG;
+use crate::positions;
+use crate::protocol::{Control, ErrorCode, Event};
+use crate::queries::{self, Database};
+use crate::routes::{registered_handler, send_event};
+
+// the last time each character attacked
+pub static COOLDOWNS: Lazy<Mutex<HashMap<i32,Instant>>> = Lazy::new(|| { Default::default() });
+
+/// The state of one side of a fight, as known by the server
+#[derive(Clone, Debug, PartialEq)]
+pub struct Combatant {
+    pub id: i32,
+    pub x: f32,
+    pub y: f32,
+    pub health: i32,
+    pub damage: i32,
+}
+
+impl Combatant {
+    pub fn distance(&self, x: f32, y: f32) -> f32 {
+        ((self.x - x).powi(2) + (self.y - y).powi(2)).sqrt()
+    }
+}
+
+/// Why an attack didn't happen
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Miss {
+    /// The attacker targeted themselves
+    SelfTarget,
+    /// The target is too far away
+    OutOfRange,
+    /// The target is already dead
+    Dead,
+}
+
+/// The result of an attack that landed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hit {
+    pub damage: i32,
+    pub health: i32,
+    pub killed: bool,
+}
+
+/// Work out what happens when `attacker` attacks `target`
+pub fn resolve(attacker: &Combatant, target: &Combatant, range: f32) -> Result<Hit, Miss> {
+    if attacker.id == target.id {
+        return Err(Miss::SelfTarget);
+    }
+
+    if target.health <= 0 {
+        return Err(Miss::Dead);
+    }
+
+    if attacker.distance(target.x, target.y) > range {
+        return Err(Miss::OutOfRange);
+    }
+
+    let damage = attacker.damage.min(target.health);
+    let health = target.health - damage;
+
+    Ok(Hit {
+        damage,
+        health,
+        killed: health <= 0,
+    })
+}
+
+// start the cooldown for an attacker, returning false if it's still
+// cooling down from the last attack.
+async fn use_cooldown(attacker_id: i32, now: Instant) -> bool {
+    let mut cooldowns = COOLDOWNS.lock().await;
+    match cooldowns.get(&attacker_id) {
+        Some(last) if now.duration_since(*last) < CONFIG.attack_cooldown => false,
+        _ => {
+            cooldowns.insert(attacker_id, now);
+            true
+        }
+    }
+}
+
+// load the server's view of a character for combat
+async fn combatant(database: &Database, character_id: i32) -> Option<Combatant> {
+    let character = queries::fetch_characters(database, vec![character_id])
+        .await
+        .ok()?
+        .pop()?;
+    let stats = queries::fetch_stats(database, character_id).await.ok()?;
+
+    // positions in memory are newer than the ones in the database
+    let (x, y) = positions::current(character.id)
+        .await
+        .unwrap_or((character.x, character.y));
+
+    Some(Combatant {
+        id: character.id,
+        x,
+        y,
+        health: stats.health,
+        damage: stats.damage,
+    })
+}
+
+/// The error to answer an attack with if its target isn't connected,
+/// the same as a whisper to someone who isn't online
+pub async fn offline(message: &Message) -> Option<Control> {
+    let Value::Attack(m) = &message.value else {
+        return None;
+    };
+
+    match registered_handler(m.target).await {
+        true => None,
+        false => Some(Control::Error {
+            code: ErrorCode::UnknownRecipient,
+            message: format!("{} is not online", m.target),
+            id: Some(message.id()),
+        }),
+    }
+}
+
+/// Apply an attack, persist the result, and tell everyone nearby about it
+pub async fn attack(database: &Database, attacker_id: i32, target_id: i32) {
+    // the target could have left while the attack was queued
+    if !registered_handler(target_id).await {
+        return;
+    }
+
+    if !use_cooldown(attacker_id, Instant::now()).await {
+        return;
+    }
+
+    let (Some(attacker), Some(target)) = (
+        combatant(database, attacker_id).await,
+        combatant(database, target_id).await,
+    ) else {
+        return;
+    };
+
+    let Ok(hit) = resolve(&attacker, &target, CONFIG.attack_range) else {
+        return;
+    };
+
+    let mut recipients = positions::nearby(database, target.x, target.y, CONFIG.event_range).await;
+    recipients.extend([attacker.id, target.id]);
+    recipients.sort();
+    recipients.dedup();
+
+    send_event(&recipients, Event::Hit {
+        attacker: attacker.id,
+        target: target.id,
+        damage: hit.damage,
+        health: hit.health,
+    }).await;
+
+    if !hit.killed {
+        let _ = queries::update_health(database, target.id, hit.health).await;
+        return;
+    }
+
+    // the target died, so move them back to the spawn point
+    let _ = queries::record_kill(database, attacker.id, target.id).await;
+    positions::record(target.id, CONFIG.spawn_x, CONFIG.spawn_y).await;
+
+    let stats = queries::fetch_stats(database, target.id).await;
+
+    send_event(&recipients, Event::Death {
+        target: target.id,
+        killer: attacker.id,
+    }).await;
+
+    send_event(&recipients, Event::Respawn {
+        target: target.id,
+        x: CONFIG.spawn_x,
+        y: CONFIG.spawn_y,
+        health: stats.map(|s| s.health).unwrap_or_default(),
+    }).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn combatant(id: i32, x: f32, health: i32) -> Combatant {
+        Combatant {
+            id,
+            x,
+            y: 0.0,
+            health,
+            damage: 10,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_resolve_hit() {
+        let hit = resolve(&combatant(1, 0.0, 100), &combatant(2, 1.0, 100), 2.0);
+        assert_eq!(hit, Ok(Hit { damage: 10, health: 90, killed: false }));
+    }
+
+    #[actix_web::test]
+    async fn test_resolve_kill() {
+        // damage is capped at the remaining health
+        let hit = resolve(&combatant(1, 0.0, 100), &combatant(2, 1.0, 5), 2.0);
+        assert_eq!(hit, Ok(Hit { damage: 5, health: 0, killed: true }));
+    }
+
+    #[actix_web::test]
+    async fn test_resolve_miss1() {
+        let hit = resolve(&combatant(1, 0.0, 100), &combatant(2, 3.0, 100), 2.0);
+        assert_eq!(hit, Err(Miss::OutOfRange));
+    }
+
+    #[actix_web::test]
+    async fn test_resolve_miss2() {
+        let hit = resolve(&combatant(1, 0.0, 100), &combatant(1, 0.0, 100), 2.0);
+        assert_eq!(hit, Err(Miss::SelfTarget));
+    }
+
+    #[actix_web::test]
+    async fn test_resolve_miss3() {
+        let hit = resolve(&combatant(1, 0.0, 100), &combatant(2, 1.0, 0), 2.0);
+        assert_eq!(hit, Err(Miss::Dead));
+    }
+
+    #[actix_web::test]
+    async fn test_offline() {
+        let attack = Message::Attack(-14, -15);
+        match offline(&attack).await {
+            Some(Control::Error { code, id, .. }) => {
+                assert_eq!(code, ErrorCode::UnknownRecipient);
+                assert_eq!(id, Some(attack.id()));
+            }
+            other => panic!("expected an error, got {:?}", other),
+        }
+
+        // connected targets can be attacked
+        crate::routes::register_handler(crate::payloads::AccountInfo {
+            id: -15,
+            username: "TARGET".into(),
+            role: crate::auth::Role::Player,
+            session: None,
+            key: None,
+        }).await;
+        assert!(offline(&attack).await.is_none());
+        crate::routes::unregister_handler(-15).await;
+    }
+
+    #[actix_web::test]
+    async fn test_use_cooldown() {
+        let now = Instant::now();
+        assert!(use_cooldown(-1, now).await);
+        assert!(!use_cooldown(-1, now).await);
+        assert!(use_cooldown(-1, now + CONFIG.attack_cooldown).await);
+    }
+}
@@ -0,0 +1,314 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+
+use crate::limits::Rate;
+use crate::moderation::Filter;
+
+pub static CONFIG: Lazy<Config> = Lazy::new(Config::load);
+
+/// Deployment settings, read from the environment (or `.env`) once
+/// on first use. Every setting has a default so only `DATABASE_URL`
+/// is required to run the server.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// How often the server pings each socket connection
+    pub heartbeat_interval: Duration,
+    /// How many pings can go unanswered before a client is disconnected
+    pub heartbeat_misses: u32,
+    /// How long a new connection has to send its hello
+    pub handshake_timeout: Duration,
+    /// How long a dropped connection can be resumed before the character leaves
+    pub resume_grace: Duration,
+    /// What to do when an account that is already connected connects again
+    pub duplicate_login: DuplicateLogin,
+    /// The largest frame (in bytes) a client can send
+    pub max_frame_size: usize,
+    /// How many client messages can wait in the incoming queue
+    pub incoming_capacity: usize,
+    /// How often each client can send move messages
+    pub move_rate: Rate,
+    /// How often each client can send attack messages
+    pub attack_rate: Rate,
+    /// How often each client can send chat messages
+    pub chat_rate: Rate,
+    /// How often each client can send any other message
+    pub other_rate: Rate,
+    /// How often a client can go over its limits before being disconnected
+    pub strike_rate: Rate,
+    /// How many unreadable frames a client can send before being disconnected
+    pub malformed_limit: u32,
+    /// How often moved characters have their positions written to the database
+    pub flush_interval: Duration,
+    /// How close an attacker has to be to hit their target
+    pub attack_range: f32,
+    /// How long a character has to wait between attacks
+    pub attack_cooldown: Duration,
+    /// How close a player has to be to an event to be told about it
+    pub event_range: f32,
+    /// Where characters appear after they die
+    pub spawn_x: f32,
+    pub spawn_y: f32,
+    /// The longest chat message (in characters) a player can send
+    pub chat_length: usize,
+    /// How close a player has to be to hear local chat
+    pub chat_range: f32,
+    /// Whether chat messages are saved to the database
+    pub chat_history: bool,
+    /// Words that are masked out of chat messages and party names
+    pub chat_filter: Filter,
+    /// How many recent messages from a player are attached to a report
+    pub report_snapshot: usize,
+    /// How many logins can fail before an address or username is locked out
+    pub login_attempts: u32,
+    /// The first lockout after too many failed logins, doubled for each failure after
+    pub login_lockout: Duration,
+    /// The longest lockout after failed logins
+    pub login_lockout_max: Duration,
+    /// Whether to take client addresses from proxy headers (only safe behind a proxy)
+    pub trust_proxy: bool,
+    /// Usernames nobody can register, ignoring case
+    pub reserved_names: Filter,
+    /// The least strength (0 to 4) a new password needs
+    pub password_strength: u32,
+    /// How much memory (in KiB) hashing a password takes
+    pub argon2_memory: u32,
+    /// How many passes hashing a password makes over its memory
+    pub argon2_iterations: u32,
+    /// How many lanes hashing a password uses
+    pub argon2_parallelism: u32,
+    /// How long a password reset token can be used for
+    pub reset_expiry: Duration,
+    /// How many password resets can be requested for a username before it's locked out
+    pub reset_requests: u32,
+    /// How long a username is locked out after too many reset requests
+    pub reset_lockout: Duration,
+    /// The file outgoing mail is written to by the file mailer
+    pub mail_log: String,
+    /// The name authenticator apps show two factor codes under
+    pub totp_issuer: String,
+    /// How long a login has to be finished with a two factor code
+    pub challenge_expiry: Duration,
+    /// How long a deleted account can be restored (by logging in) before it's purged
+    pub deletion_grace: Duration,
+    /// How often accounts past their deletion grace period are purged
+    pub purge_interval: Duration,
+}
+
+/// The policy for a second socket connection to an account
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateLogin {
+    /// Close the existing session and keep the new one
+    #[default]
+    Kick,
+    /// Refuse the new session and keep the existing one
+    Reject,
+}
+
+impl FromStr for DuplicateLogin {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "kick" => Ok(Self::Kick),
+            "reject" => Ok(Self::Reject),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval: Duration::from_secs(5),
+            heartbeat_misses: 3,
+            handshake_timeout: Duration::from_secs(5),
+            resume_grace: Duration::from_secs(30),
+            duplicate_login: DuplicateLogin::default(),
+            max_frame_size: 64 * 1024,
+            incoming_capacity: 10_000,
+            move_rate: Rate::new(30.0, 60.0),
+            attack_rate: Rate::new(5.0, 10.0),
+            chat_rate: Rate::new(1.0, 5.0),
+            other_rate: Rate::new(5.0, 10.0),
+            strike_rate: Rate::new(1.0, 20.0),
+            malformed_limit: 10,
+            flush_interval: Duration::from_millis(1000),
+            attack_range: 2.0,
+            attack_cooldown: Duration::from_millis(1000),
+            event_range: 50.0,
+            spawn_x: 0.0,
+            spawn_y: 0.0,
+            chat_length: 256,
+            chat_range: 20.0,
+            chat_history: false,
+            chat_filter: Filter::default(),
+            report_snapshot: 20,
+            login_attempts: 5,
+            login_lockout: Duration::from_secs(1),
+            login_lockout_max: Duration::from_secs(15 * 60),
+            trust_proxy: false,
+            reserved_names: "admin,administrator,system,server,moderator,mod,support,staff,root,official,gm"
+                .parse()
+                .unwrap_or_default(),
+            password_strength: 2,
+            argon2_memory: argon2::Params::DEFAULT_M_COST,
+            argon2_iterations: argon2::Params::DEFAULT_T_COST,
+            argon2_parallelism: argon2::Params::DEFAULT_P_COST,
+            reset_expiry: Duration::from_secs(60 * 60),
+            reset_requests: 3,
+            reset_lockout: Duration::from_secs(15 * 60),
+            mail_log: "mail.log".into(),
+            totp_issuer: "Tinker".into(),
+            challenge_expiry: Duration::from_secs(5 * 60),
+            deletion_grace: Duration::from_secs(30 * 24 * 60 * 60),
+            purge_interval: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Self {
+        let default = Self::default();
+        let config = Self {
+            heartbeat_interval: Duration::from_millis(var(
+                "HEARTBEAT_INTERVAL_MS",
+                default.heartbeat_interval.as_millis() as u64,
+            )),
+            heartbeat_misses: var("HEARTBEAT_MISSES", default.heartbeat_misses),
+            handshake_timeout: Duration::from_millis(var(
+                "HANDSHAKE_TIMEOUT_MS",
+                default.handshake_timeout.as_millis() as u64,
+            )),
+            resume_grace: Duration::from_millis(var(
+                "RESUME_GRACE_MS",
+                default.resume_grace.as_millis() as u64,
+            )),
+            duplicate_login: var("DUPLICATE_LOGIN", default.duplicate_login),
+            max_frame_size: var("MAX_FRAME_SIZE", default.max_frame_size),
+            incoming_capacity: var("INCOMING_CAPACITY", default.incoming_capacity),
+            move_rate: var("MOVE_RATE", default.move_rate),
+            attack_rate: var("ATTACK_RATE", default.attack_rate),
+            chat_rate: var("CHAT_RATE", default.chat_rate),
+            other_rate: var("OTHER_RATE", default.other_rate),
+            strike_rate: var("STRIKE_RATE", default.strike_rate),
+            malformed_limit: var("MALFORMED_LIMIT", default.malformed_limit),
+            flush_interval: Duration::from_millis(var(
+                "FLUSH_INTERVAL_MS",
+                default.flush_interval.as_millis() as u64,
+            )),
+            attack_range: var("ATTACK_RANGE", default.attack_range),
+            attack_cooldown: Duration::from_millis(var(
+                "ATTACK_COOLDOWN_MS",
+                default.attack_cooldown.as_millis() as u64,
+            )),
+            event_range: var("EVENT_RANGE", default.event_range),
+            spawn_x: var("SPAWN_X", default.spawn_x),
+            spawn_y: var("SPAWN_Y", default.spawn_y),
+            chat_length: var("CHAT_LENGTH", default.chat_length),
+            chat_range: var("CHAT_RANGE", default.chat_range),
+            chat_history: var("CHAT_HISTORY", default.chat_history),
+            chat_filter: var("CHAT_FILTER", default.chat_filter),
+            report_snapshot: var("REPORT_SNAPSHOT", default.report_snapshot),
+            login_attempts: var("LOGIN_ATTEMPTS", default.login_attempts),
+            login_lockout: Duration::from_millis(var(
+                "LOGIN_LOCKOUT_MS",
+                default.login_lockout.as_millis() as u64,
+            )),
+            login_lockout_max: Duration::from_millis(var(
+                "LOGIN_LOCKOUT_MAX_MS",
+                default.login_lockout_max.as_millis() as u64,
+            )),
+            trust_proxy: var("TRUST_PROXY", default.trust_proxy),
+            reserved_names: var("RESERVED_NAMES", default.reserved_names),
+            password_strength: var("PASSWORD_STRENGTH", default.password_strength),
+            argon2_memory: var("ARGON2_MEMORY_KIB", default.argon2_memory),
+            argon2_iterations: var("ARGON2_ITERATIONS", default.argon2_iterations),
+            argon2_parallelism: var("ARGON2_PARALLELISM", default.argon2_parallelism),
+            reset_expiry: Duration::from_millis(var(
+                "RESET_EXPIRY_MS",
+                default.reset_expiry.as_millis() as u64,
+            )),
+            reset_requests: var("RESET_REQUESTS", default.reset_requests),
+            reset_lockout: Duration::from_millis(var(
+                "RESET_LOCKOUT_MS",
+                default.reset_lockout.as_millis() as u64,
+            )),
+            mail_log: var("MAIL_LOG", default.mail_log),
+            totp_issuer: var("TOTP_ISSUER", default.totp_issuer),
+            challenge_expiry: Duration::from_millis(var(
+                "CHALLENGE_EXPIRY_MS",
+                default.challenge_expiry.as_millis() as u64,
+            )),
+            deletion_grace: Duration::from_millis(var(
+                "DELETION_GRACE_MS",
+                default.deletion_grace.as_millis() as u64,
+            )),
+            purge_interval: Duration::from_millis(var(
+                "PURGE_INTERVAL_MS",
+                default.purge_interval.as_millis() as u64,
+            )),
+        };
+        config.check();
+        config
+    }
+
+    // settings that can't fall back to a default without changing how
+    // data is stored stop the server instead
+    fn check(&self) {
+        if let Err(error) = self.argon2_params() {
+            panic!("invalid ARGON2 settings: {}", error);
+        }
+    }
+
+    /// The cost new passwords are hashed with
+    pub fn argon2_params(&self) -> Result<argon2::Params, argon2::Error> {
+        argon2::Params::new(
+            self.argon2_memory,
+            self.argon2_iterations,
+            self.argon2_parallelism,
+            None,
+        )
+    }
+}
+
+// read and parse a variable, falling back to the default if it's
+// missing. one that's set but can't be parsed stops the server rather
+// than quietly running with the default.
+fn var<T: FromStr>(name: &str, default: T) -> T {
+    match dotenv::var(name) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("invalid {} setting: {:?}", name, value)),
+        Err(_) => default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        Config::default().check();
+    }
+
+    #[test]
+    fn test_var() {
+        assert_eq!(var("TEST_VAR_MISSING", 64u32), 64);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid TEST_VAR_MALFORMED setting")]
+    fn test_var_malformed() {
+        std::env::set_var("TEST_VAR_MALFORMED", "64mb");
+        var("TEST_VAR_MALFORMED", 64u32);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid ARGON2 settings")]
+    fn test_check_argon2() {
+        Config { argon2_parallelism: 0, ..Config::default() }.check();
+    }
+}
@@ -1,4 +1,7 @@
 use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde_json::json;
+
+use crate::models::BanSelect;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -16,6 +19,15 @@ pub enum Error {
     #[error("Could not [de]serialize data")]
     SerializationError(#[from] serde_json::Error),
 
+    #[error("Could not encode binary data")]
+    EncodeError(#[from] rmp_serde::encode::Error),
+
+    #[error("Could not decode binary data")]
+    DecodeError(#[from] rmp_serde::decode::Error),
+
+    #[error("The socket session was closed")]
+    SessionClosed(#[from] actix_ws::Closed),
+
     #[error("Could not [en|de]code data for token")]
     TokenError(#[from] branca::errors::Error),
 
@@ -24,6 +36,30 @@ pub enum Error {
 
     #[error("No character currently selected")]
     NoCharacter,
+
+    #[error("The username or password is wrong")]
+    InvalidCredentials,
+
+    #[error("Too many failed attempts")]
+    Locked(std::time::Duration),
+
+    #[error("A valid login token is required")]
+    Unauthorized,
+
+    #[error("Not allowed to perform this action")]
+    Forbidden,
+
+    #[error("The account is not connected")]
+    NotConnected,
+
+    #[error("This account is banned")]
+    Banned(BanSelect),
+
+    #[error("The reset token is invalid or has expired")]
+    InvalidReset,
+
+    #[error("The two factor code is wrong")]
+    InvalidCode,
 }
 
 impl From<argon2::password_hash::Error> for Error {
@@ -38,12 +74,75 @@ impl ResponseError for Error {
             Self::ValidationError(_) => StatusCode::UNPROCESSABLE_ENTITY,
             Self::PasshwordHashError(_) => StatusCode::UNPROCESSABLE_ENTITY,
             Self::SerializationError(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::EncodeError(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::DecodeError(_) => StatusCode::UNPROCESSABLE_ENTITY,
             Self::TokenError(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::DatabaseError(diesel::result::Error::NotFound) => StatusCode::NOT_FOUND,
+            Self::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            Self::Locked(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::NotConnected => StatusCode::NOT_FOUND,
+            Self::Banned(_) => StatusCode::FORBIDDEN,
+            Self::InvalidReset => StatusCode::BAD_REQUEST,
+            Self::InvalidCode => StatusCode::UNAUTHORIZED,
             _ => StatusCode::INTERNAL_SERVER_ERROR
         }
     }
 
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code()).body(self.to_string())
+        match self {
+            // each invalid field is listed so forms can show what's wrong
+            Self::ValidationError(errors) => {
+                let fields = errors
+                    .field_errors()
+                    .into_iter()
+                    .map(|(field, errors)| {
+                        let errors = errors
+                            .iter()
+                            .map(|e| json!({
+                                "code": e.code,
+                                "message": e.message,
+                            }))
+                            .collect::<Vec<_>>();
+                        (field.to_string(), json!(errors))
+                    })
+                    .collect::<serde_json::Map<_, _>>();
+
+                HttpResponse::build(self.status_code()).json(json!({
+                    "code": "invalid",
+                    "message": self.to_string(),
+                    "fields": fields,
+                }))
+            },
+            // locked out clients are told when to try again
+            Self::Locked(wait) => {
+                let seconds = wait.as_secs_f64().ceil() as u64;
+                HttpResponse::build(self.status_code())
+                    .insert_header(("Retry-After", seconds.to_string()))
+                    .json(json!({
+                        "code": "locked",
+                        "message": self.to_string(),
+                        "retry_after": seconds,
+                    }))
+            },
+            // clients need to tell missing logins from missing permissions
+            Self::Unauthorized => HttpResponse::build(self.status_code()).json(json!({
+                "code": "unauthorized",
+                "message": self.to_string(),
+            })),
+            Self::Forbidden => HttpResponse::build(self.status_code()).json(json!({
+                "code": "forbidden",
+                "message": self.to_string(),
+            })),
+            // banned clients are told why, and for how long
+            Self::Banned(ban) => HttpResponse::build(self.status_code()).json(json!({
+                "code": "banned",
+                "message": self.to_string(),
+                "reason": ban.reason,
+                "until": ban.until,
+            })),
+            _ => HttpResponse::build(self.status_code()).body(self.to_string())
+        }
     }
 }
@@ -0,0 +1,117 @@
+use std::time::{Duration, Instant};
+
+/// Tracks pings sent to a single socket connection and the pongs that
+/// come back, so that half-open connections can be detected.
+#[derive(Clone, Debug)]
+pub struct Heartbeat {
+    interval: Duration,
+    limit: u32,
+    last: Instant,
+    sent: Option<Instant>,
+    missed: u32,
+    latency: Option<Duration>,
+}
+
+impl Heartbeat {
+    pub fn new(interval: Duration, limit: u32) -> Self {
+        Self {
+            interval,
+            limit,
+            last: Instant::now(),
+            sent: None,
+            missed: 0,
+            latency: None,
+        }
+    }
+
+    /// Check if it's time to send another ping
+    pub fn due(&self, now: Instant) -> bool {
+        now.duration_since(self.last) >= self.interval
+    }
+
+    /// Record a ping sent at `now`, counting the previous one as
+    /// missed if it was never answered.
+    pub fn ping(&mut self, now: Instant) {
+        if self.sent.is_some() {
+            self.missed += 1;
+        }
+        self.sent = Some(now);
+        self.last = now;
+    }
+
+    /// Record a pong received at `now`
+    pub fn pong(&mut self, now: Instant) {
+        if let Some(sent) = self.sent.take() {
+            self.latency = Some(now.duration_since(sent));
+        }
+        self.missed = 0;
+    }
+
+    /// Check if the client has missed too many pings
+    pub fn expired(&self) -> bool {
+        self.missed >= self.limit
+    }
+
+    /// The round-trip time of the most recently answered ping
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_millis(100);
+
+    #[actix_web::test]
+    async fn test_heartbeat_due() {
+        let start = Instant::now();
+        let mut heartbeat = Heartbeat::new(INTERVAL, 3);
+        heartbeat.ping(start);
+
+        assert!(!heartbeat.due(start + INTERVAL / 2));
+        assert!(heartbeat.due(start + INTERVAL));
+    }
+
+    #[actix_web::test]
+    async fn test_heartbeat_latency() {
+        let start = Instant::now();
+        let mut heartbeat = Heartbeat::new(INTERVAL, 3);
+        assert_eq!(heartbeat.latency(), None);
+
+        heartbeat.ping(start);
+        heartbeat.pong(start + Duration::from_millis(20));
+
+        assert_eq!(heartbeat.latency(), Some(Duration::from_millis(20)));
+    }
+
+    #[actix_web::test]
+    async fn test_heartbeat_expired1() {
+        // three pings go unanswered
+        let start = Instant::now();
+        let mut heartbeat = Heartbeat::new(INTERVAL, 3);
+
+        for i in 0..4 {
+            assert!(!heartbeat.expired());
+            heartbeat.ping(start + INTERVAL * i);
+        }
+
+        assert!(heartbeat.expired());
+    }
+
+    #[actix_web::test]
+    async fn test_heartbeat_expired2() {
+        // a pong resets the missed count
+        let start = Instant::now();
+        let mut heartbeat = Heartbeat::new(INTERVAL, 3);
+
+        for i in 0..3 {
+            heartbeat.ping(start + INTERVAL * i);
+        }
+        heartbeat.pong(start + INTERVAL * 3);
+        heartbeat.ping(start + INTERVAL * 4);
+
+        assert!(!heartbeat.expired());
+    }
+}
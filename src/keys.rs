@@ -0,0 +1,212 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use crate::auth;
+use crate::errors::{Error, Result};
+use crate::limits::Kind;
+use crate::models::ApiKeySelect;
+use crate::payloads::AccountInfo;
+use crate::protocol::Frame;
+use crate::queries::{self, Database};
+use crate::utilities;
+
+/// What every api key starts with, to tell it apart from a login token
+pub const PREFIX: &str = "tk_";
+
+// ------------------------------------------------
+// Scope
+
+/// Something an api key is allowed to do. Login tokens can do all of
+/// them, so these only ever limit keys.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyScope {
+    /// Open a socket and receive the game state
+    Connect,
+    /// Send moves, attacks and party requests over the socket
+    Play,
+    /// Send chat over the socket
+    Chat,
+    /// Report players
+    Report,
+}
+
+impl KeyScope {
+    /// The name the scope is stored under
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Connect => "connect",
+            Self::Play => "play",
+            Self::Chat => "chat",
+            Self::Report => "report",
+        }
+    }
+
+    /// The scope a key needs to send a frame over the socket
+    pub fn of(frame: &Frame) -> Self {
+        match Kind::of(frame) {
+            Kind::Chat => Self::Chat,
+            _ => Self::Play,
+        }
+    }
+
+    /// Read stored scopes, skipping any that are no longer known
+    pub fn parse(scopes: &str) -> Vec<Self> {
+        scopes
+            .split_whitespace()
+            .filter_map(|s| s.parse().ok())
+            .collect()
+    }
+
+    /// Write scopes the way they're stored
+    pub fn join(scopes: &[Self]) -> String {
+        scopes
+            .iter()
+            .map(|s| s.name())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl FromStr for KeyScope {
+    type Err = ();
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "connect" => Ok(Self::Connect),
+            "play" => Ok(Self::Play),
+            "chat" => Ok(Self::Chat),
+            "report" => Ok(Self::Report),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The api key an account was authenticated with, and what it allows
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct KeyGrant {
+    pub id: i32,
+    pub scopes: Vec<KeyScope>,
+}
+// ------------------------------------------------
+
+// ------------------------------------------------
+// Keys
+
+// the hash a secret is stored as. secrets are random, so a fast hash is
+// enough and keys can be checked on every request without slowing down.
+fn digest(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Issue a new key for a bot character, returning it along with the
+/// secret to give to the tool. Only a hash of the secret is kept, so
+/// it can't be shown again.
+pub async fn issue(
+    database: &Database,
+    character_id: i32,
+    name: &str,
+    scopes: &[KeyScope],
+    created_by: Option<i32>,
+) -> Result<(ApiKeySelect, String)> {
+    let secret = utilities::random_uuid().simple().to_string();
+    let hash = digest(&secret);
+
+    let key = queries::create_api_key(
+        database,
+        character_id,
+        name.to_string(),
+        hash,
+        KeyScope::join(scopes),
+        created_by,
+    ).await?;
+
+    let token = format!("{}{}_{}", PREFIX, key.id, secret);
+    Ok((key, token))
+}
+
+/// The account a key acts for. Fails with 401 if the key is unknown,
+/// revoked or doesn't match.
+pub async fn authenticate(database: &Database, token: &str) -> Result<AccountInfo> {
+    let (id, secret) = token
+        .strip_prefix(PREFIX)
+        .and_then(|t| t.split_once('_'))
+        .and_then(|(id, secret)| Some((id.parse::<i32>().ok()?, secret)))
+        .ok_or(Error::Unauthorized)?;
+
+    let key = queries::fetch_api_key(database, id)
+        .await?
+        .filter(|k| k.revoked.is_none())
+        .ok_or(Error::Unauthorized)?;
+
+    let matches: bool = key.key_hash.as_bytes().ct_eq(digest(secret).as_bytes()).into();
+    if !matches {
+        return Err(Error::Unauthorized);
+    }
+
+    queries::touch_api_key(database, key.id).await?;
+    let character = queries::fetch_character_by_id(database, key.character_id).await?;
+
+    Ok(AccountInfo {
+        id: character.id,
+        username: character.username,
+        role: auth::fetch_role(database, character.id).await?,
+        session: None,
+        key: Some(KeyGrant {
+            id: key.id,
+            scopes: KeyScope::parse(&key.scopes),
+        }),
+    })
+}
+
+/// Whether a bearer token is an api key rather than a login token
+pub fn is_key(token: &str) -> bool {
+    token.starts_with(PREFIX)
+}
+// ------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    #[actix_web::test]
+    async fn test_scopes() {
+        let scopes = KeyScope::parse("connect CHAT unknown");
+        assert_eq!(scopes, vec![KeyScope::Connect, KeyScope::Chat]);
+        assert_eq!(KeyScope::join(&scopes), "connect chat");
+    }
+
+    #[actix_web::test]
+    async fn test_authenticate() {
+        let database = "test_authenticate";
+        test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+
+        let character = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+        queries::create_bot(&pool, character.id, None).await.unwrap();
+
+        let (key, token) = issue(&pool, character.id, "BOT", &[KeyScope::Connect], None).await.unwrap();
+        let account = authenticate(&pool, &token).await.unwrap();
+        assert_eq!(account.id, character.id);
+        assert!(account.allows(KeyScope::Connect));
+        assert!(!account.allows(KeyScope::Chat));
+
+        // the secret has to match
+        let (id, _) = token.rsplit_once('_').unwrap();
+        assert!(authenticate(&pool, &format!("{}_nonsense", id)).await.is_err());
+
+        // and revoked keys stop working
+        assert_eq!(queries::revoke_api_key(&pool, key.id).await.unwrap(), 1);
+        assert_eq!(queries::revoke_api_key(&pool, key.id).await.unwrap(), 0);
+        assert!(authenticate(&pool, &token).await.is_err());
+
+        test_utils::teardown(database);
+    }
+}
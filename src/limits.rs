@@ -0,0 +1,236 @@
+use std::str::FromStr;
+use std::time::Instant;
+
+use tinker_records::messages::Value;
+
+use crate::protocol::{Frame, Request};
+
+// ------------------------------------------------
+// Rate
+
+/// A sustained rate with an allowance for short bursts, written as
+/// `<per_second>/<burst>` in the environment (e.g. `20/40`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rate {
+    pub per_second: f64,
+    pub burst: f64,
+}
+
+impl Rate {
+    pub const fn new(per_second: f64, burst: f64) -> Self {
+        Self { per_second, burst }
+    }
+}
+
+impl FromStr for Rate {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (rate, burst) = value.split_once('/').ok_or(())?;
+        Ok(Self {
+            per_second: rate.trim().parse().map_err(|_| ())?,
+            burst: burst.trim().parse().map_err(|_| ())?,
+        })
+    }
+}
+// ------------------------------------------------
+
+// ------------------------------------------------
+// Bucket
+
+/// A token bucket that refills continuously at a fixed rate
+#[derive(Clone, Debug)]
+pub struct Bucket {
+    rate: Rate,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    pub fn new(rate: Rate, now: Instant) -> Self {
+        Self {
+            rate,
+            tokens: rate.burst,
+            updated: now,
+        }
+    }
+
+    /// Take a single token, returning false if the bucket is empty
+    pub fn take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate.per_second).min(self.rate.burst);
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+// ------------------------------------------------
+
+// ------------------------------------------------
+// Limiter
+
+/// The categories of client messages that are limited separately
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Move,
+    Attack,
+    Chat,
+    Other,
+}
+
+impl Kind {
+    pub fn of(frame: &Frame) -> Self {
+        match frame {
+            Frame::Message(m) => match m.value {
+                Value::Move(_) => Self::Move,
+                Value::Attack(_) => Self::Attack,
+                _ => Self::Other,
+            },
+            Frame::Request(Request::Chat { .. }) => Self::Chat,
+            Frame::Request(_) => Self::Other,
+        }
+    }
+}
+
+/// What to do with a message from a client
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// The message is within limits
+    Accept,
+    /// The message is over the limit and should be ignored
+    Drop,
+    /// The client keeps going over the limit and should be disconnected
+    Disconnect,
+}
+
+/// Per-connection limits for incoming messages. Every dropped message
+/// uses up a strike, and a client that runs out of strikes is dropped.
+#[derive(Clone, Debug)]
+pub struct Limiter {
+    moves: Bucket,
+    attacks: Bucket,
+    chat: Bucket,
+    other: Bucket,
+    strikes: Bucket,
+}
+
+impl Limiter {
+    pub fn new(
+        moves: Rate,
+        attacks: Rate,
+        chat: Rate,
+        other: Rate,
+        strikes: Rate,
+        now: Instant,
+    ) -> Self {
+        Self {
+            moves: Bucket::new(moves, now),
+            attacks: Bucket::new(attacks, now),
+            chat: Bucket::new(chat, now),
+            other: Bucket::new(other, now),
+            strikes: Bucket::new(strikes, now),
+        }
+    }
+
+    pub fn check(&mut self, kind: Kind, now: Instant) -> Verdict {
+        let bucket = match kind {
+            Kind::Move => &mut self.moves,
+            Kind::Attack => &mut self.attacks,
+            Kind::Chat => &mut self.chat,
+            Kind::Other => &mut self.other,
+        };
+
+        if bucket.take(now) {
+            Verdict::Accept
+        } else if self.strikes.take(now) {
+            Verdict::Drop
+        } else {
+            Verdict::Disconnect
+        }
+    }
+}
+// ------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const RATE: Rate = Rate::new(10.0, 5.0);
+
+    fn limiter(now: Instant) -> Limiter {
+        Limiter::new(RATE, RATE, RATE, RATE, Rate::new(1.0, 3.0), now)
+    }
+
+    #[actix_web::test]
+    async fn test_rate_parse1() {
+        assert_eq!("20/40".parse(), Ok(Rate::new(20.0, 40.0)));
+        assert_eq!("0.5 / 2".parse(), Ok(Rate::new(0.5, 2.0)));
+    }
+
+    #[actix_web::test]
+    async fn test_rate_parse2() {
+        assert!("20".parse::<Rate>().is_err());
+        assert!("a/b".parse::<Rate>().is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_bucket_burst() {
+        // a full bucket allows a burst and then nothing
+        let now = Instant::now();
+        let mut bucket = Bucket::new(RATE, now);
+
+        for _ in 0..5 {
+            assert!(bucket.take(now));
+        }
+        assert!(!bucket.take(now));
+    }
+
+    #[actix_web::test]
+    async fn test_bucket_refill() {
+        // an empty bucket refills at the given rate
+        let now = Instant::now();
+        let mut bucket = Bucket::new(RATE, now);
+
+        while bucket.take(now) {}
+
+        let later = now + Duration::from_millis(200);
+        assert!(bucket.take(later));
+        assert!(bucket.take(later));
+        assert!(!bucket.take(later));
+    }
+
+    #[actix_web::test]
+    async fn test_limiter_kinds() {
+        // using up moves doesn't limit attacks
+        let now = Instant::now();
+        let mut limiter = limiter(now);
+
+        for _ in 0..5 {
+            assert_eq!(limiter.check(Kind::Move, now), Verdict::Accept);
+        }
+        assert_eq!(limiter.check(Kind::Move, now), Verdict::Drop);
+        assert_eq!(limiter.check(Kind::Attack, now), Verdict::Accept);
+        assert_eq!(limiter.check(Kind::Chat, now), Verdict::Accept);
+    }
+
+    #[actix_web::test]
+    async fn test_limiter_escalation() {
+        // dropped messages escalate to a disconnect
+        let now = Instant::now();
+        let mut limiter = limiter(now);
+
+        for _ in 0..5 {
+            assert_eq!(limiter.check(Kind::Move, now), Verdict::Accept);
+        }
+        for _ in 0..3 {
+            assert_eq!(limiter.check(Kind::Move, now), Verdict::Drop);
+        }
+        assert_eq!(limiter.check(Kind::Move, now), Verdict::Disconnect);
+    }
+}
@@ -0,0 +1,81 @@
+use std::fs::OpenOptions;
+use std::io::{Result, Write};
+use std::path::PathBuf;
+
+use chrono::Utc;
+
+/// An email to send to an account
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mail {
+    /// Accounts don't have email addresses yet, so mail is addressed
+    /// to a username and it's up to the mailer to find where it goes.
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Something that can deliver mail. The server is given one as app data
+/// (`web::Data<dyn Mailer>`), so it can be swapped out per deployment.
+pub trait Mailer: Send + Sync {
+    /// Deliver a message. This may block, so it's called off the runtime.
+    fn send(&self, mail: &Mail) -> Result<()>;
+}
+
+/// Appends mail to a local file instead of sending it, for development
+/// and testing without a mail server.
+#[derive(Clone, Debug)]
+pub struct FileMailer {
+    path: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new<T: Into<PathBuf>>(path: T) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, mail: &Mail) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+
+        writeln!(file, "Date: {}", Utc::now().to_rfc3339())?;
+        writeln!(file, "To: {}", mail.to)?;
+        writeln!(file, "Subject: {}", mail.subject)?;
+        writeln!(file)?;
+        writeln!(file, "{}", mail.body)?;
+        writeln!(file)?;
+
+        println!("MAILED {} TO {}", mail.subject, mail.to);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_file_mailer() {
+        let path = std::env::temp_dir().join("test_file_mailer.log");
+        let _ = std::fs::remove_file(&path);
+
+        let mailer = FileMailer::new(&path);
+        for subject in ["FIRST", "SECOND"] {
+            mailer.send(&Mail {
+                to: "USERNAME".into(),
+                subject: subject.into(),
+                body: "BODY".into(),
+            }).unwrap();
+        }
+
+        // mail is appended rather than overwritten
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(contents.contains("Subject: FIRST"));
+        assert!(contents.contains("Subject: SECOND"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
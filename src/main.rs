@@ -1,16 +1,43 @@
+use std::sync::Arc;
+
 use diesel::{r2d2::ConnectionManager, PgConnection};
+use diesel_migrations::MigrationHarness;
 use actix_web::{web, App, HttpServer};
 use dotenv;
+use once_cell::sync::Lazy;
+use config::CONFIG;
+use mailer::{FileMailer, Mailer};
 use utilities::process_messages;
 
+mod account;
+mod admin;
+mod auth;
 mod payloads;
+mod positions;
+mod chat;
+mod combat;
+mod config;
 mod errors;
+mod heartbeat;
+mod keys;
+mod limits;
+mod mailer;
+mod models;
+mod moderation;
+mod protocol;
 mod queries;
 mod routes;
+mod schema;
+mod throttle;
+mod totp;
 mod utilities;
+mod validation;
 
 #[cfg(test)]
 pub mod test_utils {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
     use diesel_migrations::MigrationHarness;
     use diesel::{r2d2::ConnectionManager, Connection,RunQueryDsl,PgConnection}; 
     use actix_web::{dev::Service, test, web, App};
@@ -18,9 +45,15 @@ pub mod test_utils {
     use url::Url;
     use tinker_records::tests::MIGRATIONS;
 
-    use crate::{queries::Database, utilities};
+    use crate::{mailer::{FileMailer, Mailer}, queries::{self, Database}, utilities};
+    use crate::{auth::Role, payloads::AccountInfo};
     
     const SQL: &str = include_str!("../assets/setup.sql");
+
+    // where mail sent while testing against a database is written
+    pub fn mail_log(database: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}.mail", database))
+    }
     
     pub async fn pool(database: &str) -> Database {
         let mut url = Url::parse(&dotenv::var("DATABASE_URL").unwrap()).unwrap();
@@ -33,6 +66,21 @@ pub mod test_utils {
             .expect("could not build connection pool")
     }
 
+    // a token for a character like the one a login returns, with its
+    // own session
+    pub async fn token(pool: &Database, id: i32, role: Role) -> String {
+        let character = queries::fetch_character_by_id(pool, id).await.unwrap();
+        let session = queries::create_session(pool, id, None, None).await.unwrap();
+
+        utilities::token::encode(&AccountInfo {
+            id,
+            username: character.username,
+            role,
+            session: Some(session.id),
+            key: None,
+        }).unwrap()
+    }
+
     pub async fn setup(database: &str) -> impl Service<Request, Response = actix_web::dev::ServiceResponse, Error = actix_web::Error> {
         // get the test database url
         dotenv::dotenv().unwrap();
@@ -55,6 +103,7 @@ pub mod test_utils {
     
         // run all migrations
         conn.run_pending_migrations(MIGRATIONS).expect("Could not run migrations");
+        conn.run_pending_migrations(queries::MIGRATIONS).expect("Could not run server migrations");
     
         // create and insert a hashed password
         let password = utilities::password::hash("PASSWORD").unwrap();
@@ -64,6 +113,10 @@ pub mod test_utils {
         let query = diesel::sql_query(sql);
         query.execute(&mut conn).expect(&format!("Could not create records {}", database));
     
+        // start each test with no mail
+        let _ = std::fs::remove_file(mail_log(database));
+        let mailer: Arc<dyn Mailer> = Arc::new(FileMailer::new(mail_log(database)));
+
         // build a connection manager for the test database
         let mgr = ConnectionManager::<PgConnection>::new(url);
         
@@ -76,9 +129,20 @@ pub mod test_utils {
         test::init_service(
             App::new()
                 .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::from(mailer))
                 .service(crate::routes::login)
+                .service(crate::routes::verify_login)
                 .service(crate::routes::register)
+                .service(crate::routes::profile)
+                .service(crate::routes::profile_by_name)
+                .service(crate::routes::online)
+                .service(crate::routes::characters_within)
+                .service(crate::routes::chat_history)
+                .service(crate::routes::report_player)
+                .service(crate::account::scope())
+                .service(crate::admin::scope())
                 .service(crate::routes::connect)
+                .service(crate::routes::connect_bearer)
         ).await
     }
     
@@ -125,6 +189,9 @@ pub mod test_utils {
 async fn main() -> std::io::Result<()> {
     dotenv::dotenv().unwrap();
 
+    // bad settings stop the server before it accepts anyone
+    Lazy::force(&CONFIG);
+
     let url = dotenv::var("DATABASE_URL").unwrap();
     let mgr = ConnectionManager::<PgConnection>::new(url);
 
@@ -132,19 +199,50 @@ async fn main() -> std::io::Result<()> {
         .build(mgr)
         .expect("could not build connection pool");
 
+    // create or update the tables owned by the server
+    pool.get()
+        .expect("could not get a database connection")
+        .run_pending_migrations(queries::MIGRATIONS)
+        .expect("could not run migrations");
+
+    // nobody can be online before the server has started
+    queries::reset_presence(&pool)
+        .await
+        .expect("could not reset online characters");
+
     // start the message processing background task
     process_messages(pool.clone());
+    let database = pool.clone();
+
+    // mail is written to a file until there's a real mailer
+    let mailer: Arc<dyn Mailer> = Arc::new(FileMailer::new(&CONFIG.mail_log));
 
     // TODO: use the configure method to add resources and abstract
     //       app construction into a standalone method: https://docs.rs/actix-web/latest/actix_web/struct.App.html#method.configure
     HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::from(mailer.clone()))
             .service(routes::login)
+            .service(routes::verify_login)
             .service(routes::register)
+            .service(routes::profile)
+            .service(routes::profile_by_name)
+            .service(routes::online)
+            .service(routes::characters_within)
+            .service(routes::chat_history)
+            .service(routes::report_player)
+            .service(account::scope())
+            .service(admin::scope())
             .service(routes::connect)
+            .service(routes::connect_bearer)
     })
     .bind(("127.0.0.1", 8080))?
     .run()
-    .await
+    .await?;
+
+    // the server stops on the same signals as the background task, so
+    // write any positions that haven't been saved before exiting
+    positions::flush(&database).await;
+    Ok(())
 }
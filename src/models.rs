@@ -0,0 +1,238 @@
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// ------------------------------------------------
+// Stats
+#[derive(Queryable, Selectable, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[diesel(table_name = crate::schema::character_stats)]
+pub struct StatsSelect {
+    pub character_id: i32,
+    pub health: i32,
+    pub max_health: i32,
+    pub damage: i32,
+    pub kills: i32,
+    pub deaths: i32,
+    pub modified: DateTime<Utc>,
+}
+// ------------------------------------------------
+
+// ------------------------------------------------
+// Profile
+/// The public columns of a character, leaving out the password hash
+#[derive(Queryable, Selectable, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[diesel(table_name = tinker_records::schema::characters)]
+pub struct ProfileSelect {
+    pub id: i32,
+    pub username: String,
+    pub x: f32,
+    pub y: f32,
+    pub created: DateTime<Utc>,
+}
+// ------------------------------------------------
+
+// ------------------------------------------------
+// Presence
+#[derive(Queryable, Selectable, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[diesel(table_name = crate::schema::character_presence)]
+pub struct PresenceSelect {
+    pub character_id: i32,
+    pub online: bool,
+    pub last_seen: Option<DateTime<Utc>>,
+    /// Total time spent connected, in seconds
+    pub play_time: i64,
+}
+// ------------------------------------------------
+
+// ------------------------------------------------
+// Chat
+#[derive(Queryable, Selectable, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[diesel(table_name = crate::schema::chat_messages)]
+pub struct ChatSelect {
+    pub id: i64,
+    pub sender_id: i32,
+    pub sender: String,
+    pub channel: String,
+    pub recipient_id: Option<i32>,
+    pub party: Option<String>,
+    pub text: String,
+    pub sent: DateTime<Utc>,
+}
+
+#[derive(Insertable, Clone, Debug)]
+#[diesel(table_name = crate::schema::chat_messages)]
+pub struct ChatInsert {
+    pub sender_id: i32,
+    pub sender: String,
+    pub channel: String,
+    pub recipient_id: Option<i32>,
+    pub party: Option<String>,
+    pub text: String,
+    pub sent: DateTime<Utc>,
+}
+// ------------------------------------------------
+
+// ------------------------------------------------
+// Login attempts
+#[derive(Queryable, Selectable, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[diesel(table_name = crate::schema::login_attempts)]
+pub struct LoginAttemptSelect {
+    pub id: i64,
+    pub username: String,
+    pub address: Option<String>,
+    /// The account the username belongs to, if it exists
+    pub character_id: Option<i32>,
+    pub reason: String,
+    pub created: DateTime<Utc>,
+}
+
+#[derive(Queryable, Selectable, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[diesel(table_name = crate::schema::login_sessions)]
+pub struct SessionSelect {
+    pub id: i64,
+    pub character_id: i32,
+    pub created: DateTime<Utc>,
+    /// When the session was logged out, or `None` if it's still valid
+    pub revoked: Option<DateTime<Utc>>,
+    /// The name the client gave its device, or its user agent
+    pub device: Option<String>,
+    pub address: Option<String>,
+    pub last_used: DateTime<Utc>,
+}
+
+#[derive(Queryable, Selectable, Clone, Debug, PartialEq)]
+#[diesel(table_name = crate::schema::password_resets)]
+pub struct ResetSelect {
+    pub id: i32,
+    pub character_id: i32,
+    pub token_hash: String,
+    pub created: DateTime<Utc>,
+    pub expires: DateTime<Utc>,
+    /// When the reset was used (or made unusable by another change)
+    pub used: Option<DateTime<Utc>>,
+}
+
+impl ResetSelect {
+    pub fn usable(&self, now: DateTime<Utc>) -> bool {
+        self.used.is_none() && self.expires > now
+    }
+}
+// ------------------------------------------------
+
+// ------------------------------------------------
+// Two factor
+#[derive(Queryable, Selectable, Clone, Debug, PartialEq)]
+#[diesel(table_name = crate::schema::account_totp)]
+pub struct TotpSelect {
+    pub character_id: i32,
+    /// The base32 encoded secret
+    pub secret: String,
+    pub created: DateTime<Utc>,
+    /// When setup was confirmed with a code, or `None` while pending
+    pub enabled: Option<DateTime<Utc>>,
+    pub last_step: Option<i64>,
+}
+
+#[derive(Queryable, Selectable, Clone, Debug, PartialEq)]
+#[diesel(table_name = crate::schema::recovery_codes)]
+pub struct RecoveryCodeSelect {
+    pub id: i32,
+    pub character_id: i32,
+    pub code_hash: String,
+    pub used: Option<DateTime<Utc>>,
+}
+// ------------------------------------------------
+
+// ------------------------------------------------
+// Deletion
+#[derive(Queryable, Selectable, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[diesel(table_name = crate::schema::account_deletions)]
+pub struct DeletionSelect {
+    pub character_id: i32,
+    pub requested: DateTime<Utc>,
+    /// When the account is deleted for good
+    pub purge_after: DateTime<Utc>,
+}
+// ------------------------------------------------
+
+// ------------------------------------------------
+// Api keys
+#[derive(Queryable, Selectable, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[diesel(table_name = crate::schema::bot_characters)]
+pub struct BotSelect {
+    pub character_id: i32,
+    pub created_by: Option<i32>,
+    pub created: DateTime<Utc>,
+}
+
+#[derive(Queryable, Selectable, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[diesel(table_name = crate::schema::api_keys)]
+pub struct ApiKeySelect {
+    pub id: i32,
+    pub character_id: i32,
+    pub name: String,
+    pub key_hash: String,
+    /// Space separated, e.g. `connect play`
+    pub scopes: String,
+    pub created_by: Option<i32>,
+    pub created: DateTime<Utc>,
+    pub last_used: Option<DateTime<Utc>>,
+    pub revoked: Option<DateTime<Utc>>,
+}
+// ------------------------------------------------
+
+// ------------------------------------------------
+// Moderation
+#[derive(Queryable, Selectable, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[diesel(table_name = crate::schema::account_mutes)]
+pub struct MuteSelect {
+    pub character_id: i32,
+    pub reason: String,
+    /// When the mute ends, or `None` if it doesn't
+    pub until: Option<DateTime<Utc>>,
+    pub created: DateTime<Utc>,
+}
+
+impl MuteSelect {
+    pub fn active(&self, now: DateTime<Utc>) -> bool {
+        self.until.map(|until| until > now).unwrap_or(true)
+    }
+}
+
+#[derive(Queryable, Selectable, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[diesel(table_name = crate::schema::account_bans)]
+pub struct BanSelect {
+    pub id: i32,
+    pub character_id: i32,
+    pub reason: String,
+    /// The admin that issued the ban, if it was issued by an account
+    pub issued_by: Option<i32>,
+    pub created: DateTime<Utc>,
+    /// When the ban ends, or `None` if it's permanent
+    pub until: Option<DateTime<Utc>>,
+    pub revoked: Option<DateTime<Utc>>,
+}
+
+#[derive(Queryable, Selectable, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[diesel(table_name = crate::schema::reports)]
+pub struct ReportSelect {
+    pub id: i32,
+    pub reporter_id: i32,
+    /// `None` once the reported account has been deleted
+    pub target_id: Option<i32>,
+    pub reason: String,
+    pub created: DateTime<Utc>,
+    pub resolved: Option<DateTime<Utc>>,
+    pub resolution: Option<String>,
+}
+
+#[derive(Queryable, Selectable, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[diesel(table_name = crate::schema::report_messages)]
+pub struct ReportMessageSelect {
+    pub id: i64,
+    pub report_id: i32,
+    pub channel: String,
+    pub text: String,
+    pub sent: DateTime<Utc>,
+}
+// ------------------------------------------------
@@ -0,0 +1,171 @@
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use futures_util::lock::Mutex;
+use once_cell::sync::Lazy;
+
+use crate::config::CONFIG;
+use crate::errors::{Error, Result};
+use crate::queries::{self, Database};
+
+// the most recent chat messages sent by each character, kept so that
+// reports can include them even if chat history isn't saved.
+pub static RECENT: Lazy<Mutex<HashMap<i32,VecDeque<Recent>>>> = Lazy::new(|| { Default::default() });
+
+// ------------------------------------------------
+// Filter
+
+/// A list of words, written as a comma separated list in the
+/// environment (e.g. `foo,bar`). Used to mask words out of
+/// player-authored text and to reserve usernames.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Filter(Vec<String>);
+
+impl FromStr for Filter {
+    type Err = ();
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(Self(value
+            .split(',')
+            .map(|w| w.trim().to_lowercase())
+            .filter(|w| !w.is_empty())
+            .collect()))
+    }
+}
+
+impl Filter {
+    /// Replace every filtered word in the text with asterisks. Only
+    /// whole words are matched, ignoring case.
+    pub fn apply(&self, text: &str) -> String {
+        if self.0.is_empty() {
+            return text.to_string();
+        }
+
+        let mut result = String::with_capacity(text.len());
+        let mut word = String::new();
+
+        for c in text.chars() {
+            if c.is_alphanumeric() {
+                word.push(c);
+            } else {
+                result.push_str(&self.mask(&word));
+                result.push(c);
+                word.clear();
+            }
+        }
+
+        result.push_str(&self.mask(&word));
+        result
+    }
+
+    /// Check if a whole word is in the list, ignoring case
+    pub fn matches(&self, word: &str) -> bool {
+        self.0.contains(&word.to_lowercase())
+    }
+
+    fn mask(&self, word: &str) -> String {
+        match self.matches(word) {
+            true => "*".repeat(word.chars().count()),
+            false => word.to_string(),
+        }
+    }
+}
+// ------------------------------------------------
+
+// ------------------------------------------------
+// Recent
+
+/// A chat message as it was sent, before filtering
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recent {
+    pub channel: String,
+    pub text: String,
+    pub sent: DateTime<Utc>,
+}
+
+/// Remember a message sent by a character, forgetting the oldest
+/// once more than `report_snapshot` are kept.
+pub async fn remember(character_id: i32, channel: &str, text: &str, sent: DateTime<Utc>) {
+    let mut recent = RECENT.lock().await;
+    let messages = recent.entry(character_id).or_default();
+
+    messages.push_back(Recent {
+        channel: channel.to_string(),
+        text: text.to_string(),
+        sent,
+    });
+
+    while messages.len() > CONFIG.report_snapshot {
+        messages.pop_front();
+    }
+}
+
+/// The recent messages sent by a character, oldest first
+pub async fn recent(character_id: i32) -> Vec<Recent> {
+    RECENT.lock().await
+        .get(&character_id)
+        .map(|m| m.iter().cloned().collect())
+        .unwrap_or_default()
+}
+// ------------------------------------------------
+
+/// Check if a character is currently muted, returning when the mute
+/// ends (`None` if it doesn't).
+pub async fn muted(database: &Database, character_id: i32) -> Option<Option<DateTime<Utc>>> {
+    queries::fetch_mute(database, character_id)
+        .await
+        .ok()
+        .flatten()
+        .filter(|m| m.active(Utc::now()))
+        .map(|m| m.until)
+}
+
+/// Fail with the ban keeping a character out, if there is one
+pub async fn check_ban(database: &Database, character_id: i32) -> Result<()> {
+    match queries::fetch_active_ban(database, character_id).await? {
+        Some(ban) => Err(Error::Banned(ban)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_filter_parse() {
+        let filter: Filter = " Foo, bar ,,".parse().unwrap();
+        assert_eq!(filter, Filter(vec!["foo".into(), "bar".into()]));
+    }
+
+    #[actix_web::test]
+    async fn test_filter_apply1() {
+        // whole words are masked whatever their case
+        let filter: Filter = "foo,bar".parse().unwrap();
+        assert_eq!(filter.apply("FOO you, bar!"), "*** you, ***!");
+    }
+
+    #[actix_web::test]
+    async fn test_filter_apply2() {
+        // words that only contain a filtered word are left alone
+        let filter: Filter = "foo".parse().unwrap();
+        assert_eq!(filter.apply("food foo"), "food ***");
+        assert_eq!(Filter::default().apply("foo"), "foo");
+    }
+
+    #[actix_web::test]
+    async fn test_remember() {
+        // only the newest messages are kept
+        let now = Utc::now();
+        for i in 0..CONFIG.report_snapshot + 5 {
+            remember(-20, "global", &i.to_string(), now).await;
+        }
+
+        let messages = recent(-20).await;
+        assert_eq!(messages.len(), CONFIG.report_snapshot);
+        assert_eq!(messages[0].text, "5");
+
+        RECENT.lock().await.remove(&-20);
+    }
+}
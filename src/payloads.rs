@@ -1,11 +1,26 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 use validator::Validate;
 
+use crate::auth::Role;
+use crate::keys::{KeyGrant, KeyScope};
+use crate::positions::Shape;
+use crate::models::{
+    ApiKeySelect, ChatSelect, DeletionSelect, LoginAttemptSelect, PresenceSelect,
+    ReportMessageSelect, ReportSelect, SessionSelect, StatsSelect,
+};
+
 // ------------------------------------------------
 // Forms
 #[derive(Deserialize, Serialize, Clone, Debug, Validate)]
 pub struct Register {
-    #[validate(length(min = 4, max = 32), does_not_contain(pattern = " "))]
+    /// Normalized with `validation::normalize` before it's validated
+    #[validate(
+        length(min = 4, max = 32),
+        does_not_contain(pattern = " "),
+        custom(function = "crate::validation::username")
+    )]
     pub username: String,
     #[validate(
         length(min = 8, max = 256),
@@ -28,6 +43,228 @@ pub struct Login {
     #[validate(length(min = 8, max = 256), does_not_contain(pattern = " "))]
     pub password: String,
 }
+
+#[derive(Deserialize, Serialize, Clone, Debug, Validate)]
+pub struct ChangePassword {
+    #[validate(length(min = 8, max = 256), does_not_contain(pattern = " "))]
+    pub current: String,
+    #[validate(
+        length(min = 8, max = 256),
+        must_match(other = "password2"),
+        does_not_contain(pattern = " ")
+    )]
+    pub password1: String,
+    #[validate(
+        length(min = 8, max = 256),
+        must_match(other = "password1"),
+        does_not_contain(pattern = " ")
+    )]
+    pub password2: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Validate)]
+pub struct RequestReset {
+    #[validate(length(min = 4, max = 32), does_not_contain(pattern = " "))]
+    pub username: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Validate)]
+pub struct ConfirmReset {
+    /// The token from the reset mail
+    #[validate(length(min = 1, max = 256))]
+    pub token: String,
+    #[validate(
+        length(min = 8, max = 256),
+        must_match(other = "password2"),
+        does_not_contain(pattern = " ")
+    )]
+    pub password1: String,
+    #[validate(
+        length(min = 8, max = 256),
+        must_match(other = "password1"),
+        does_not_contain(pattern = " ")
+    )]
+    pub password2: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Validate)]
+pub struct TotpCode {
+    /// A code from an authenticator app, or a recovery code
+    #[validate(length(min = 6, max = 32))]
+    pub code: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Validate)]
+pub struct DisableTotp {
+    #[validate(length(min = 8, max = 256))]
+    pub password: String,
+    #[validate(length(min = 6, max = 32))]
+    pub code: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Validate)]
+pub struct VerifyLogin {
+    /// The challenge returned by `/login`
+    #[validate(length(min = 1, max = 512))]
+    pub challenge: String,
+    #[validate(length(min = 6, max = 32))]
+    pub code: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Validate)]
+pub struct DeleteAccount {
+    /// The current password, so a stolen token can't delete the account
+    #[validate(length(min = 8, max = 256))]
+    pub password: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Validate)]
+pub struct Report {
+    /// The character being reported
+    pub target: i32,
+    #[validate(length(min = 1, max = 512))]
+    pub reason: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Validate)]
+pub struct Resolve {
+    #[validate(length(min = 1, max = 512))]
+    pub resolution: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Validate)]
+pub struct Kick {
+    /// Sent as the close reason, which can't be longer than 123 bytes
+    #[validate(length(min = 1), custom(function = "crate::validation::close_reason"))]
+    pub reason: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Teleport {
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Validate)]
+pub struct Broadcast {
+    #[validate(length(min = 1, max = 1024))]
+    pub text: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Validate)]
+pub struct Ban {
+    #[validate(length(min = 1, max = 512))]
+    pub reason: String,
+    /// How long the ban lasts (at most ten years), or forever if missing
+    #[validate(range(min = 1, max = 5_256_000))]
+    pub minutes: Option<i64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Validate)]
+pub struct SetRole {
+    pub role: Role,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Validate)]
+pub struct CreateBot {
+    /// Normalized with `validation::normalize` before it's validated
+    #[validate(
+        length(min = 4, max = 32),
+        does_not_contain(pattern = " "),
+        custom(function = "crate::validation::username")
+    )]
+    pub username: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Validate)]
+pub struct CreateApiKey {
+    /// What the key is for, e.g. the tool that uses it
+    #[validate(length(min = 1, max = 64))]
+    pub name: String,
+    #[validate(length(min = 1))]
+    pub scopes: Vec<KeyScope>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Validate)]
+pub struct Mute {
+    #[validate(length(min = 1, max = 512))]
+    pub reason: String,
+    /// How long the mute lasts (at most ten years), or forever if missing
+    #[validate(range(min = 1, max = 5_256_000))]
+    pub minutes: Option<i64>,
+}
+// ------------------------------------------------
+
+// ------------------------------------------------
+// Query
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Connect {
+    pub resume: Option<Uuid>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, Validate)]
+pub struct History {
+    /// Only return messages older than this message id
+    pub before: Option<i64>,
+    #[validate(range(min = 1, max = 100))]
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, Validate)]
+pub struct Page {
+    /// Only return characters with a greater id than this
+    pub after: Option<i32>,
+    #[validate(range(min = 1, max = 100))]
+    pub limit: Option<i64>,
+}
+
+/// Either the corners of a rectangle (`x1`, `y1`, `x2`, `y2`) or the
+/// center and radius of a circle (`x`, `y`, `radius`)
+#[derive(Deserialize, Serialize, Clone, Debug, Default, Validate)]
+#[validate(schema(function = "crate::validation::area"))]
+pub struct Area {
+    pub x1: Option<f32>,
+    pub y1: Option<f32>,
+    pub x2: Option<f32>,
+    pub y2: Option<f32>,
+    pub x: Option<f32>,
+    pub y: Option<f32>,
+    pub radius: Option<f32>,
+    /// Only return characters with a greater id than this
+    pub after: Option<i32>,
+    #[validate(range(min = 1, max = 100))]
+    pub limit: Option<i64>,
+}
+
+impl Area {
+    /// The shape that was asked for, if exactly one was given in full
+    pub fn shape(&self) -> Option<Shape> {
+        let rectangle = match (self.x1, self.y1, self.x2, self.y2) {
+            (Some(x1), Some(y1), Some(x2), Some(y2)) => Some(Shape::rectangle(x1, y1, x2, y2)),
+            (None, None, None, None) => None,
+            _ => return None,
+        };
+
+        let circle = match (self.x, self.y, self.radius) {
+            (Some(x), Some(y), Some(radius)) => Some(Shape::Circle { x, y, radius }),
+            (None, None, None) => None,
+            _ => return None,
+        };
+
+        match (rectangle, circle) {
+            (Some(shape), None) | (None, Some(shape)) => Some(shape),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Reports {
+    /// List resolved reports instead of open ones
+    #[serde(default)]
+    pub resolved: bool,
+}
 // ------------------------------------------------
 
 // ------------------------------------------------
@@ -35,17 +272,211 @@ pub struct Login {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct AccountInfo {
     pub id: i32,
-    pub username: String
+    pub username: String,
+    #[serde(default)]
+    pub role: Role,
+    /// The login session the token belongs to. Tokens from a login
+    /// always have one, so they stop working once it's revoked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<i64>,
+    /// The api key the account was authenticated with. Keys are never
+    /// turned into tokens, so this is only set on requests made with one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<KeyGrant>,
+}
+
+impl AccountInfo {
+    /// Whether the account may do something, which is only limited
+    /// when it was authenticated with an api key
+    pub fn allows(&self, scope: KeyScope) -> bool {
+        self.key
+            .as_ref()
+            .map(|k| k.scopes.contains(&scope))
+            .unwrap_or(true)
+    }
+}
+
+/// A login that's waiting for a two factor code. This is never given
+/// to clients as it is, only as an encrypted token.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LoginChallenge {
+    pub challenge_for: i32,
+    pub expires: DateTime<Utc>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct AccountKey {
     pub id: i32,
     pub name: String,
+    pub role: Role,
     pub token: String
 }
 // ------------------------------------------------
 
+// ------------------------------------------------
+// Responses
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Profile {
+    pub id: i32,
+    pub username: String,
+    pub x: f32,
+    pub y: f32,
+    pub online: bool,
+    pub last_seen: Option<DateTime<Utc>>,
+    /// Total time spent connected, in seconds
+    pub play_time: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CharacterPage {
+    /// In order of id
+    pub characters: Vec<Profile>,
+    /// The `after` value for the next page, if there is one
+    pub next: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChatPage {
+    /// The newest messages first
+    pub messages: Vec<ChatSelect>,
+    /// The `before` value for the next (older) page, if there is one
+    pub next: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Player {
+    pub id: i32,
+    pub username: String,
+    pub x: f32,
+    pub y: f32,
+    /// The last measured round trip time, in milliseconds
+    pub latency: Option<u64>,
+    /// The connection dropped and is waiting to be resumed
+    pub lingering: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct QueueDepths {
+    pub incoming: usize,
+    pub outgoing: usize,
+    pub database: usize,
+    /// Events waiting to be sent, across every connection
+    pub events: usize,
+    pub connected: usize,
+    pub lingering: usize,
+    /// Moves that were replaced in memory before they had to be written
+    pub writes_saved: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReportDetail {
+    pub report: ReportSelect,
+    /// The reported player's messages from just before the report
+    pub messages: Vec<ReportMessageSelect>,
+}
+
+/// Returned by `/login` instead of an `AccountKey` when the account
+/// has two factor enabled, to be sent back with a code.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Challenge {
+    pub challenge: String,
+    pub expires: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TotpSetup {
+    /// The base32 secret, for entering by hand
+    pub secret: String,
+    /// The `otpauth://` uri, for showing as a QR code
+    pub uri: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RecoveryCodes {
+    /// Each code can be used once instead of a two factor code
+    pub codes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Bot {
+    pub id: i32,
+    pub username: String,
+    pub created_by: Option<i32>,
+    pub created: DateTime<Utc>,
+}
+
+/// An api key, without its hash
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ApiKey {
+    pub id: i32,
+    pub character_id: i32,
+    pub name: String,
+    pub scopes: Vec<KeyScope>,
+    pub created_by: Option<i32>,
+    pub created: DateTime<Utc>,
+    pub last_used: Option<DateTime<Utc>>,
+    pub revoked: Option<DateTime<Utc>>,
+}
+
+impl From<ApiKeySelect> for ApiKey {
+    fn from(key: ApiKeySelect) -> Self {
+        Self {
+            id: key.id,
+            character_id: key.character_id,
+            name: key.name,
+            scopes: KeyScope::parse(&key.scopes),
+            created_by: key.created_by,
+            created: key.created,
+            last_used: key.last_used,
+            revoked: key.revoked,
+        }
+    }
+}
+
+/// A key that was just created, which is the only time it's shown
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NewApiKey {
+    #[serde(flatten)]
+    pub info: ApiKey,
+    /// Sent as `Authorization: Bearer <key>`, or in place of the token
+    /// when connecting
+    pub key: String,
+}
+
+/// A login session, as listed to the account that owns it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SessionInfo {
+    pub id: i64,
+    pub device: Option<String>,
+    pub address: Option<String>,
+    pub created: DateTime<Utc>,
+    pub last_used: DateTime<Utc>,
+    /// Whether this is the session the request was made with
+    pub current: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Export {
+    pub id: i32,
+    pub username: String,
+    pub role: Role,
+    pub created: DateTime<Utc>,
+    pub modified: DateTime<Utc>,
+    pub x: f32,
+    pub y: f32,
+    pub stats: StatsSelect,
+    pub presence: Option<PresenceSelect>,
+    /// Every chat message the account sent, if chat history is saved
+    pub chat: Vec<ChatSelect>,
+    pub sessions: Vec<SessionSelect>,
+    /// Every login attempt made with the account's username
+    pub logins: Vec<LoginAttemptSelect>,
+    /// The pending deletion of the account, if there is one
+    pub deletion: Option<DeletionSelect>,
+    pub exported: DateTime<Utc>,
+}
+// ------------------------------------------------
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -166,3 +597,4 @@ mod tests {
         assert!(form.validate().is_err());
     }
 }
+
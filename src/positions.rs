@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use futures_util::lock::Mutex;
+use once_cell::sync::Lazy;
+
+use crate::queries::{self, Database};
+use crate::routes::REGISTRY;
+
+// the latest known position of each character
+pub static POSITIONS: Lazy<Mutex<HashMap<i32,Position>>> = Lazy::new(|| { Default::default() });
+
+// the number of positions received and the number written to the database
+pub static RECEIVED: AtomicU64 = AtomicU64::new(0);
+pub static WRITTEN: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Position {
+    pub x: f32,
+    pub y: f32,
+    /// The position hasn't been written to the database yet
+    pub dirty: bool,
+}
+
+/// An area of the world to search for characters
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shape {
+    Rectangle { min_x: f32, min_y: f32, max_x: f32, max_y: f32 },
+    Circle { x: f32, y: f32, radius: f32 },
+}
+
+impl Shape {
+    /// A rectangle between two corners, in any order
+    pub fn rectangle(x1: f32, y1: f32, x2: f32, y2: f32) -> Self {
+        Self::Rectangle {
+            min_x: x1.min(x2),
+            min_y: y1.min(y2),
+            max_x: x1.max(x2),
+            max_y: y1.max(y2),
+        }
+    }
+
+    /// The smallest rectangle the shape fits in, as `(min_x, min_y, max_x, max_y)`
+    pub fn bounds(&self) -> (f32, f32, f32, f32) {
+        match *self {
+            Self::Rectangle { min_x, min_y, max_x, max_y } => (min_x, min_y, max_x, max_y),
+            Self::Circle { x, y, radius } => (x - radius, y - radius, x + radius, y + radius),
+        }
+    }
+
+    /// Whether a point is inside the shape, including its edge
+    pub fn contains(&self, px: f32, py: f32) -> bool {
+        match *self {
+            Self::Rectangle { min_x, min_y, max_x, max_y } => {
+                (min_x..=max_x).contains(&px) && (min_y..=max_y).contains(&py)
+            }
+            Self::Circle { x, y, radius } => {
+                (px - x) * (px - x) + (py - y) * (py - y) <= radius * radius
+            }
+        }
+    }
+}
+
+/// Record a new position for a character, to be written on the next flush
+pub async fn record(character_id: i32, x: f32, y: f32) {
+    RECEIVED.fetch_add(1, Ordering::Relaxed);
+    POSITIONS.lock().await.insert(character_id, Position { x, y, dirty: true });
+}
+
+/// The latest position of a character, if it has moved since the server
+/// started. This can be newer than the position in the database.
+pub async fn current(character_id: i32) -> Option<(f32, f32)> {
+    POSITIONS.lock().await
+        .get(&character_id)
+        .map(|p| (p.x, p.y))
+}
+
+/// Every character with a position in memory, and which of them are
+/// inside the shape by that position
+pub async fn within(shape: &Shape) -> (Vec<i32>, Vec<i32>) {
+    let positions = POSITIONS.lock().await;
+    let tracked = positions.keys().copied().collect();
+    let inside = positions
+        .iter()
+        .filter(|(_, p)| shape.contains(p.x, p.y))
+        .map(|(id, _)| *id)
+        .collect();
+    (tracked, inside)
+}
+
+/// The connected characters within `range` of a point
+pub async fn nearby(database: &Database, x: f32, y: f32, range: f32) -> Vec<i32> {
+    let connected = REGISTRY
+        .lock()
+        .await
+        .keys()
+        .copied()
+        .collect::<Vec<i32>>();
+
+    let characters = queries::fetch_characters(database, connected)
+        .await
+        .unwrap_or_default();
+
+    let mut result = Vec::new();
+    for character in characters {
+        let (cx, cy) = current(character.id)
+            .await
+            .unwrap_or((character.x, character.y));
+
+        if ((cx - x).powi(2) + (cy - y).powi(2)).sqrt() <= range {
+            result.push(character.id);
+        }
+    }
+    result
+}
+
+/// How many database writes have been avoided by coalescing positions
+pub fn writes_saved() -> u64 {
+    RECEIVED
+        .load(Ordering::Relaxed)
+        .saturating_sub(WRITTEN.load(Ordering::Relaxed))
+}
+
+// take every dirty position matching the filter, marking them clean
+async fn take_dirty<F: Fn(i32) -> bool>(filter: F) -> Vec<(i32, f32, f32)> {
+    POSITIONS.lock().await
+        .iter_mut()
+        .filter(|(id, p)| p.dirty && filter(**id))
+        .map(|(id, p)| {
+            p.dirty = false;
+            (*id, p.x, p.y)
+        })
+        .collect()
+}
+
+// mark positions dirty again after a failed write, unless they've
+// been replaced by a newer position in the meantime.
+async fn restore(positions: Vec<(i32, f32, f32)>) {
+    let mut current = POSITIONS.lock().await;
+    for (id, x, y) in positions {
+        current
+            .entry(id)
+            .and_modify(|p| p.dirty |= p.x == x && p.y == y)
+            .or_insert(Position { x, y, dirty: true });
+    }
+}
+
+// write a batch of positions in a single query
+async fn write(database: &Database, positions: Vec<(i32, f32, f32)>) -> usize {
+    if positions.is_empty() {
+        return 0;
+    }
+
+    let count = positions.len();
+    match queries::update_positions(database, positions.clone()).await {
+        Ok(_) => {
+            WRITTEN.fetch_add(count as u64, Ordering::Relaxed);
+            count
+        }
+        Err(_) => {
+            restore(positions).await;
+            0
+        }
+    }
+}
+
+/// Write every dirty position to the database
+pub async fn flush(database: &Database) -> usize {
+    let written = write(database, take_dirty(|_| true).await).await;
+    if written > 0 {
+        println!("FLUSHED {} POSITIONS ({} WRITES SAVED)", written, writes_saved());
+    }
+    written
+}
+
+/// Write a character's position to the database and stop tracking it
+pub async fn flush_character(database: &Database, character_id: i32) {
+    write(database, take_dirty(|id| id == character_id).await).await;
+    POSITIONS.lock().await.remove(&character_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_shape_bounds() {
+        // corners can be given in any order
+        let rectangle = Shape::rectangle(4.0, 1.0, 2.0, 3.0);
+        assert_eq!(rectangle.bounds(), (2.0, 1.0, 4.0, 3.0));
+
+        let circle = Shape::Circle { x: 1.0, y: 1.0, radius: 2.0 };
+        assert_eq!(circle.bounds(), (-1.0, -1.0, 3.0, 3.0));
+    }
+
+    #[actix_web::test]
+    async fn test_record_current() {
+        // the latest position replaces earlier ones
+        record(-1, 1.0, 1.0).await;
+        record(-1, 2.0, 3.0).await;
+        assert_eq!(current(-1).await, Some((2.0, 3.0)));
+        assert_eq!(current(-2).await, None);
+
+        POSITIONS.lock().await.remove(&-1);
+    }
+
+    #[actix_web::test]
+    async fn test_take_dirty() {
+        // each position is only taken once
+        record(-3, 1.0, 1.0).await;
+        record(-4, 2.0, 2.0).await;
+
+        let taken = take_dirty(|id| id == -3).await;
+        assert_eq!(taken, vec![(-3, 1.0, 1.0)]);
+        assert!(take_dirty(|id| id == -3).await.is_empty());
+
+        // but it's still known
+        assert_eq!(current(-3).await, Some((1.0, 1.0)));
+
+        POSITIONS.lock().await.remove(&-3);
+        POSITIONS.lock().await.remove(&-4);
+    }
+
+    #[actix_web::test]
+    async fn test_restore() {
+        // a failed write is retried unless the character moved again
+        record(-5, 1.0, 1.0).await;
+        record(-6, 1.0, 1.0).await;
+
+        let taken = take_dirty(|id| id == -5 || id == -6).await;
+        record(-6, 2.0, 2.0).await;
+        take_dirty(|id| id == -6).await;
+        restore(taken).await;
+
+        assert_eq!(take_dirty(|id| id == -5 || id == -6).await, vec![(-5, 1.0, 1.0)]);
+
+        POSITIONS.lock().await.remove(&-5);
+        POSITIONS.lock().await.remove(&-6);
+    }
+}
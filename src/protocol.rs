@@ -0,0 +1,608 @@
+use actix_web::http::header::{HeaderValue, SEC_WEBSOCKET_PROTOCOL};
+use actix_web::{HttpRequest, HttpResponse};
+use actix_ws::Session;
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tinker_records::messages::Message;
+use uuid::Uuid;
+
+use crate::errors::Result;
+
+// ------------------------------------------------
+// Encoding
+
+/// The wire format used for frames on a single socket connection
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// Text frames containing JSON (the default, and easiest to debug)
+    #[default]
+    Json,
+    /// Binary frames containing MessagePack
+    MessagePack,
+}
+
+impl Encoding {
+    pub const JSON: &'static str = "tinker.json";
+    pub const MESSAGE_PACK: &'static str = "tinker.msgpack";
+
+    /// The subprotocol name that selects this encoding
+    pub fn protocol(&self) -> &'static str {
+        match self {
+            Self::Json => Self::JSON,
+            Self::MessagePack => Self::MESSAGE_PACK,
+        }
+    }
+
+    /// Find the encoding for a single subprotocol name
+    pub fn from_protocol<T: AsRef<str>>(name: T) -> Option<Self> {
+        match name.as_ref().trim() {
+            Self::JSON => Some(Self::Json),
+            Self::MESSAGE_PACK => Some(Self::MessagePack),
+            _ => None,
+        }
+    }
+
+    /// Pick the first supported subprotocol requested by the client,
+    /// returning `None` if the client didn't request one we know.
+    pub fn negotiate(req: &HttpRequest) -> Option<Self> {
+        req.headers()
+            .get_all(SEC_WEBSOCKET_PROTOCOL)
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .find_map(Self::from_protocol)
+    }
+
+    /// Echo the selected subprotocol back to the client on the upgrade response
+    pub fn accept(&self, response: &mut HttpResponse) {
+        response.headers_mut().insert(
+            SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static(self.protocol()),
+        );
+    }
+
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>> {
+        Ok(match self {
+            Self::Json => serde_json::to_vec(value)?,
+            Self::MessagePack => rmp_serde::to_vec_named(value)?,
+        })
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T> {
+        Ok(match self {
+            Self::Json => serde_json::from_slice(data)?,
+            Self::MessagePack => rmp_serde::from_slice(data)?,
+        })
+    }
+
+    /// Decode a frame from a client, or build the error frame that
+    /// explains to the client why it couldn't be decoded.
+    pub fn decode_frame(&self, data: &[u8]) -> std::result::Result<Frame, Control> {
+        // requests are tagged with a type, and anything else is a message
+        let result = match self.decode::<Peek>(data) {
+            Ok(Peek { kind: Some(_) }) => self.decode_reason(data).map(Frame::Request),
+            _ => self.decode_reason(data).map(Frame::Message),
+        };
+
+        result.map_err(|message| Control::Error {
+            code: ErrorCode::Malformed,
+            message,
+            id: self.recover_id(data),
+        })
+    }
+
+    /// Decode the first frame a client sends, returning `None` if it's
+    /// neither a hello nor a message from a legacy client.
+    pub fn decode_opening(&self, data: &[u8]) -> Option<Opening> {
+        match self.decode::<Control>(data) {
+            Ok(Control::Hello(hello)) => Some(Opening::Hello(hello)),
+            Ok(_) => None,
+            Err(_) => self.decode::<Message>(data).ok().map(|_| Opening::Legacy),
+        }
+    }
+
+    // decode a value, keeping the underlying reason if it fails
+    fn decode_reason<T: DeserializeOwned>(&self, data: &[u8]) -> std::result::Result<T, String> {
+        match self {
+            Self::Json => serde_json::from_slice(data).map_err(|e| e.to_string()),
+            Self::MessagePack => rmp_serde::from_slice(data).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Try to find a message id in a frame that isn't a valid message
+    fn recover_id(&self, data: &[u8]) -> Option<Uuid> {
+        self.decode::<PeekMessage>(data)
+            .ok()
+            .map(|m| m.header.id)
+    }
+
+    /// Encode a value and send it over the session using the frame
+    /// type that matches this encoding.
+    pub async fn send<T: Serialize>(&self, session: &mut Session, value: &T) -> Result<()> {
+        match self {
+            Self::Json => session.text(serde_json::to_string(value)?).await?,
+            Self::MessagePack => session.binary(self.encode(value)?).await?,
+        };
+        Ok(())
+    }
+}
+// ------------------------------------------------
+
+// ------------------------------------------------
+// Frame
+
+/// Everything a client can send after the handshake
+#[derive(Clone, Debug, PartialEq)]
+pub enum Frame {
+    /// A game message, relayed to other players
+    Message(Message),
+    /// A request handled by the server itself
+    Request(Request),
+}
+
+/// Requests from a client that aren't relayed as game messages
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    /// Send a chat message on a channel
+    Chat { channel: Channel, text: String },
+    /// Join the party with the given name, leaving any current party.
+    /// A party that already has members needs an invite from one of them.
+    JoinParty { name: String },
+    /// Invite a connected character to the sender's party
+    InviteParty { to: String },
+    /// Leave the current party
+    LeaveParty,
+}
+
+// just enough of a frame to tell requests from messages
+#[derive(Deserialize)]
+struct Peek {
+    #[serde(rename = "type")]
+    kind: Option<String>,
+}
+
+// just enough of a message to find its id, decoded as a uuid so that
+// it's read the same way whichever encoding wrote it
+#[derive(Deserialize)]
+struct PeekMessage {
+    header: PeekHeader,
+}
+
+#[derive(Deserialize)]
+struct PeekHeader {
+    id: Uuid,
+}
+// ------------------------------------------------
+
+// ------------------------------------------------
+// Chat
+
+/// Who a chat message is sent to
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Channel {
+    /// Everyone close enough to the sender
+    Local,
+    /// Everyone connected
+    Global,
+    /// A single character, by name
+    Whisper { to: String },
+    /// Everyone in the sender's party
+    Party,
+}
+
+impl Channel {
+    /// The name the channel is stored under in the chat history
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Local => "local",
+            Self::Global => "global",
+            Self::Whisper { .. } => "whisper",
+            Self::Party => "party",
+        }
+    }
+}
+// ------------------------------------------------
+
+// ------------------------------------------------
+// Handshake
+
+/// The protocol version this server speaks natively
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// The oldest protocol version still accepted, so that clients can be
+/// updated after the server during a rollout.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// The client can reconnect with a resume token (version 2+)
+pub const FEATURE_RESUME: &str = "resume";
+
+/// Every optional feature the server supports, and the version each
+/// became available in.
+pub const FEATURES: &[(&str, u32)] = &[(FEATURE_RESUME, 2)];
+
+/// The version and optional features of one side of a connection
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Handshake {
+    pub version: u32,
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+impl Handshake {
+    /// What a legacy client that doesn't send a hello is treated as: the
+    /// oldest version, with no optional features
+    pub fn legacy() -> Self {
+        Self {
+            version: MIN_PROTOCOL_VERSION,
+            features: Vec::new(),
+        }
+    }
+
+    /// Negotiate the version and features to use with a client, or
+    /// return the reason the client can't be accepted.
+    pub fn negotiate(&self) -> std::result::Result<Self, String> {
+        if self.version < MIN_PROTOCOL_VERSION || self.version > PROTOCOL_VERSION {
+            return Err(format!(
+                "Unsupported protocol version {} (supported {}-{})",
+                self.version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+            ));
+        }
+
+        let features = FEATURES
+            .iter()
+            .filter(|(_, since)| *since <= self.version)
+            .filter(|(name, _)| self.features.iter().any(|f| f == name))
+            .map(|(name, _)| name.to_string())
+            .collect();
+
+        Ok(Self {
+            version: self.version,
+            features,
+        })
+    }
+
+    /// Check if a negotiated feature is enabled
+    pub fn has(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
+// ------------------------------------------------
+
+// ------------------------------------------------
+// Control
+
+/// Frames sent by the server about the connection itself, rather
+/// than the game state.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Control {
+    /// The first frame a client sends, describing what it supports
+    Hello(Handshake),
+    /// The server's reply to `Hello` with the version and features to use
+    Welcome(Handshake),
+    /// Something the client sent couldn't be handled. `id` is the id of
+    /// the offending message, if there was one.
+    Error {
+        code: ErrorCode,
+        message: String,
+        id: Option<Uuid>,
+    },
+    /// A token that can be passed as `?resume=<token>` when reconnecting
+    /// to pick up the session where it left off.
+    Resume { token: Uuid },
+}
+
+/// How a client started the connection
+#[derive(Clone, Debug, PartialEq)]
+pub enum Opening {
+    /// A hello describing what the client supports
+    Hello(Handshake),
+    /// A message, from a legacy client that doesn't send a hello
+    Legacy,
+}
+
+/// Machine-readable reasons for an `Error` frame
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The frame couldn't be decoded as a message
+    Malformed,
+    /// A chat message was empty or too long
+    InvalidLength,
+    /// A whisper was sent to a character that isn't connected
+    UnknownRecipient,
+    /// A party message was sent without being in a party
+    NoParty,
+    /// The sender is muted and can't chat
+    Muted,
+    /// The api key the client connected with doesn't allow the frame
+    NotPermitted,
+    /// A message was sent on behalf of another account
+    WrongAccount,
+    /// The message is one only the server sends
+    ServerOnly,
+    /// A party with members was joined without an invite
+    NotInvited,
+}
+// ------------------------------------------------
+
+// ------------------------------------------------
+// Event
+
+/// Game events the server generates itself, sent only to the players
+/// they concern rather than relayed to everyone.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// An attack landed on a target
+    Hit {
+        attacker: i32,
+        target: i32,
+        damage: i32,
+        health: i32,
+    },
+    /// A character was killed
+    Death { target: i32, killer: i32 },
+    /// A dead character reappeared at the spawn point
+    Respawn {
+        target: i32,
+        x: f32,
+        y: f32,
+        health: i32,
+    },
+    /// An admin moved a character
+    Teleport { target: i32, x: f32, y: f32 },
+    /// A message from the server operators to everyone
+    Notice { text: String, sent: DateTime<Utc> },
+    /// Another player invited you to their party
+    PartyInvite {
+        sender: i32,
+        name: String,
+        party: String,
+    },
+    /// A chat message from another player
+    Chat {
+        sender: i32,
+        name: String,
+        channel: Channel,
+        text: String,
+        sent: DateTime<Utc>,
+    },
+}
+// ------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test;
+    use chrono::Utc;
+    use glam::Vec2;
+    use tinker_records::messages::Value;
+    use tinker_records::models::CharacterSelect;
+
+    fn character() -> CharacterSelect {
+        CharacterSelect {
+            id: 1,
+            username: "TEST".into(),
+            password: "PASSWORD".into(),
+            x: 0.5,
+            y: 0.5,
+            created: Utc::now(),
+            modified: Utc::now(),
+        }
+    }
+
+    fn messages() -> Vec<Message> {
+        vec![
+            Message::Move(1, Vec2::new(0.0, 0.0), Vec2::new(1.0, 1.0)),
+            Message::Attack(1, 2),
+            Message::Initial(1, vec![character()]),
+            Message::Connect(1, character()),
+            Message::Disconnect(1, character()),
+        ]
+    }
+
+    #[actix_web::test]
+    async fn test_encoding_covers_values() {
+        // make sure every variant has a round-trip case below
+        for message in messages() {
+            match message.value {
+                Value::Move(_) => (),
+                Value::Attack(_) => (),
+                Value::Initial(_) => (),
+                Value::Connect(_) => (),
+                Value::Disconnect(_) => (),
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_encoding_json_roundtrip() {
+        for message in messages() {
+            let data = Encoding::Json.encode(&message).unwrap();
+            let result: Message = Encoding::Json.decode(&data).unwrap();
+            assert_eq!(result, message);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_encoding_msgpack_roundtrip() {
+        for message in messages() {
+            let data = Encoding::MessagePack.encode(&message).unwrap();
+            let result: Message = Encoding::MessagePack.decode(&data).unwrap();
+            assert_eq!(result, message);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_encoding_msgpack_smaller() {
+        let message = Message::Initial(1, vec![character(); 10]);
+        let json = Encoding::Json.encode(&message).unwrap();
+        let msgpack = Encoding::MessagePack.encode(&message).unwrap();
+        assert!(msgpack.len() < json.len());
+    }
+
+    #[actix_web::test]
+    async fn test_encoding_control_roundtrip() {
+        let control = Control::Resume { token: Uuid::now_v7() };
+        for encoding in [Encoding::Json, Encoding::MessagePack] {
+            let data = encoding.encode(&control).unwrap();
+            let result: Control = encoding.decode(&data).unwrap();
+            assert_eq!(result, control);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_decode_opening() {
+        let hello = Handshake { version: PROTOCOL_VERSION, features: vec![] };
+
+        for encoding in [Encoding::Json, Encoding::MessagePack] {
+            let data = encoding.encode(&Control::Hello(hello.clone())).unwrap();
+            assert_eq!(encoding.decode_opening(&data), Some(Opening::Hello(hello.clone())));
+
+            // legacy clients start with a plain message instead
+            let message = Message::Move(1, Vec2::new(0.0, 0.0), Vec2::new(1.0, 1.0));
+            let data = encoding.encode(&message).unwrap();
+            assert_eq!(encoding.decode_opening(&data), Some(Opening::Legacy));
+
+            let data = encoding.encode(&Control::Resume { token: Uuid::now_v7() }).unwrap();
+            assert_eq!(encoding.decode_opening(&data), None);
+        }
+        assert_eq!(Encoding::Json.decode_opening(b"nonsense"), None);
+    }
+
+    #[actix_web::test]
+    async fn test_decode_frame1() {
+        // a broken message reports the id it was sent with
+        #[derive(Serialize)]
+        struct Broken<'a> {
+            header: &'a tinker_records::messages::Header,
+            value: &'a str,
+        }
+
+        let message = Message::Connect(1, character());
+        let broken = Broken { header: &message.header, value: "Unknown" };
+
+        for encoding in [Encoding::Json, Encoding::MessagePack] {
+            let data = encoding.encode(&broken).unwrap();
+            match encoding.decode_frame(&data) {
+                Err(Control::Error { code, id, .. }) => {
+                    assert_eq!(code, ErrorCode::Malformed);
+                    assert_eq!(id, Some(message.id()));
+                },
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_decode_frame2() {
+        // garbage is reported without an id
+        match Encoding::Json.decode_frame(b"not a message") {
+            Err(Control::Error { code, message, id }) => {
+                assert_eq!(code, ErrorCode::Malformed);
+                assert!(!message.is_empty());
+                assert_eq!(id, None);
+            },
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_decode_frame3() {
+        // a valid message decodes normally
+        let message = Message::Connect(1, character());
+        let data = Encoding::MessagePack.encode(&message).unwrap();
+        assert_eq!(Encoding::MessagePack.decode_frame(&data), Ok(Frame::Message(message)));
+    }
+
+    #[actix_web::test]
+    async fn test_decode_frame4() {
+        // a tagged frame decodes as a request
+        let request = Request::Chat {
+            channel: Channel::Whisper { to: "NAME".into() },
+            text: "hello".into(),
+        };
+        for encoding in [Encoding::Json, Encoding::MessagePack] {
+            let data = encoding.encode(&request).unwrap();
+            assert_eq!(encoding.decode_frame(&data), Ok(Frame::Request(request.clone())));
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_decode_frame5() {
+        // a request with an unknown type is malformed
+        let data = br#"{"type":"unknown"}"#;
+        match Encoding::Json.decode_frame(data) {
+            Err(Control::Error { code, .. }) => assert_eq!(code, ErrorCode::Malformed),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_handshake_negotiate1() {
+        // the current version gets every requested feature
+        let hello = Handshake {
+            version: PROTOCOL_VERSION,
+            features: vec![FEATURE_RESUME.into(), "unknown".into()],
+        };
+        let welcome = hello.negotiate().unwrap();
+        assert_eq!(welcome.version, PROTOCOL_VERSION);
+        assert!(welcome.has(FEATURE_RESUME));
+        assert!(!welcome.has("unknown"));
+    }
+
+    #[actix_web::test]
+    async fn test_handshake_negotiate2() {
+        // the prior version is accepted without newer features
+        let hello = Handshake {
+            version: MIN_PROTOCOL_VERSION,
+            features: vec![FEATURE_RESUME.into()],
+        };
+        let welcome = hello.negotiate().unwrap();
+        assert_eq!(welcome.version, MIN_PROTOCOL_VERSION);
+        assert!(!welcome.has(FEATURE_RESUME));
+    }
+
+    #[actix_web::test]
+    async fn test_handshake_negotiate3() {
+        // versions outside the supported range are rejected
+        for version in [0, PROTOCOL_VERSION + 1] {
+            let hello = Handshake { version, features: vec![] };
+            assert!(hello.negotiate().is_err());
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_handshake_roundtrip() {
+        // features are optional in a hello
+        let hello: Control = serde_json::from_str(r#"{"type":"hello","version":2}"#).unwrap();
+        assert_eq!(hello, Control::Hello(Handshake { version: 2, features: vec![] }));
+    }
+
+    #[actix_web::test]
+    async fn test_encoding_negotiate1() {
+        // the first supported protocol is selected
+        let req = test::TestRequest::default()
+            .insert_header((SEC_WEBSOCKET_PROTOCOL, "other, tinker.msgpack, tinker.json"))
+            .to_http_request();
+        assert_eq!(Encoding::negotiate(&req), Some(Encoding::MessagePack));
+    }
+
+    #[actix_web::test]
+    async fn test_encoding_negotiate2() {
+        // no supported protocol requested
+        let req = test::TestRequest::default()
+            .insert_header((SEC_WEBSOCKET_PROTOCOL, "other"))
+            .to_http_request();
+        assert_eq!(Encoding::negotiate(&req), None);
+    }
+
+    #[actix_web::test]
+    async fn test_encoding_negotiate3() {
+        // no protocol header at all
+        let req = test::TestRequest::default().to_http_request();
+        assert_eq!(Encoding::negotiate(&req), None);
+    }
+}
@@ -1,14 +1,24 @@
 use tinker_records::models::{CharacterInsert, CharacterSelect};
+use crate::models::{
+    ApiKeySelect, BanSelect, BotSelect, ChatInsert, ChatSelect, DeletionSelect, LoginAttemptSelect, MuteSelect, PresenceSelect, ProfileSelect, RecoveryCodeSelect, ReportMessageSelect,
+    ReportSelect, ResetSelect, SessionSelect, StatsSelect, TotpSelect,
+};
 
 use actix_web::web;
 use chrono::{DateTime, Utc};
 use diesel::pg::PgConnection;
 use diesel::r2d2::ConnectionManager;
-use diesel::ExpressionMethods;
-use diesel::{query_dsl::methods::FilterDsl, RunQueryDsl};
+use diesel::{ExpressionMethods, NullableExpressionMethods, OptionalExtension};
+use diesel::{query_dsl::methods::FilterDsl, Connection, RunQueryDsl};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations};
+
+use crate::positions::Shape;
 
 pub type Database = r2d2::Pool<ConnectionManager<PgConnection>>;
 
+/// Migrations for tables owned by the server rather than tinker_records
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
 pub async fn create_character<T: ToString>(
     database: &Database,
     username: T,
@@ -46,6 +56,22 @@ pub async fn fetch_character<T: ToString>(
     .unwrap()
 }
 
+pub async fn fetch_character_by_id(
+    database: &Database,
+    character_id: i32,
+) -> diesel::QueryResult<CharacterSelect> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use tinker_records::schema::characters::dsl;
+
+        dsl::characters
+            .filter(dsl::id.eq(character_id))
+            .get_result(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
 pub async fn modified_entities(
     database: &Database,
     character_id: i32, 
@@ -81,46 +107,1671 @@ pub async fn local_entities(
     .unwrap()
 }
 
-pub async fn update_entity(
+pub async fn update_positions(
+    database: &Database,
+    positions: Vec<(i32, f32, f32)>,
+) -> diesel::QueryResult<usize> {
+    use diesel::sql_types::{Array, Float4, Int4};
+
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        let (ids, (xs, ys)): (Vec<i32>, (Vec<f32>, Vec<f32>)) = positions
+            .into_iter()
+            .map(|(id, x, y)| (id, (x, y)))
+            .unzip();
+
+        // the columns are bound as arrays and unpacked into a table of
+        // values so that every position is written in one statement.
+        diesel::sql_query("
+            UPDATE characters
+            SET x = v.x, y = v.y
+            FROM (SELECT * FROM UNNEST($1, $2, $3)) AS v(id, x, y)
+            WHERE characters.id = v.id")
+            .bind::<Array<Int4>, _>(ids)
+            .bind::<Array<Float4>, _>(xs)
+            .bind::<Array<Float4>, _>(ys)
+            .execute(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn fetch_characters(
+    database: &Database,
+    character_ids: Vec<i32>,
+) -> diesel::QueryResult<Vec<CharacterSelect>> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use tinker_records::schema::characters::dsl;
+
+        dsl::characters
+            .filter(dsl::id.eq_any(character_ids))
+            .get_results(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+// characters in their deletion grace period, which are hidden from
+// everyone else already
+fn pending_deletions(conn: &mut PgConnection) -> diesel::QueryResult<Vec<i32>> {
+    use crate::schema::account_deletions::dsl;
+    use diesel::query_dsl::methods::SelectDsl;
+
+    dsl::account_deletions
+        .select(dsl::character_id)
+        .get_results(conn)
+}
+
+/// The characters in their deletion grace period
+pub async fn fetch_pending_deletions(database: &Database) -> diesel::QueryResult<Vec<i32>> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || pending_deletions(&mut conn))
+        .await
+        .unwrap()
+}
+
+pub async fn fetch_profile(
     database: &Database,
     character_id: i32,
-    x: f32,
-    y: f32,
-) {
+) -> diesel::QueryResult<ProfileSelect> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use tinker_records::schema::characters::dsl;
+        use diesel::{query_dsl::methods::SelectDsl, SelectableHelper};
+
+        dsl::characters
+            .filter(dsl::id.eq(character_id))
+            .filter(dsl::id.ne_all(pending_deletions(&mut conn)?))
+            .select(ProfileSelect::as_select())
+            .get_result(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn fetch_profile_by_name<T: ToString>(
+    database: &Database,
+    username: T,
+) -> diesel::QueryResult<ProfileSelect> {
+    let username = username.to_string();
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use tinker_records::schema::characters::dsl;
+        use diesel::{query_dsl::methods::SelectDsl, SelectableHelper};
+
+        dsl::characters
+            .filter(dsl::username.eq(username))
+            .filter(dsl::id.ne_all(pending_deletions(&mut conn)?))
+            .select(ProfileSelect::as_select())
+            .get_result(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn fetch_profiles(
+    database: &Database,
+    character_ids: Vec<i32>,
+) -> diesel::QueryResult<Vec<ProfileSelect>> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use tinker_records::schema::characters::dsl;
+        use diesel::query_dsl::methods::{OrderDsl, SelectDsl};
+        use diesel::SelectableHelper;
+
+        dsl::characters
+            .filter(dsl::id.eq_any(character_ids))
+            .filter(dsl::id.ne_all(pending_deletions(&mut conn)?))
+            .select(ProfileSelect::as_select())
+            .order(dsl::id.asc())
+            .get_results(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+// a page of the characters saved within an area, in order of id,
+// leaving out the excluded characters
+pub async fn fetch_profiles_within(
+    database: &Database,
+    shape: Shape,
+    after: Option<i32>,
+    excluded: Vec<i32>,
+    limit: i64,
+) -> diesel::QueryResult<Vec<ProfileSelect>> {
     let mut conn = database.get().expect("No database");
     web::block(move || {
         use tinker_records::schema::characters::dsl;
+        use diesel::query_dsl::methods::{LimitDsl, OrderDsl, SelectDsl};
+        use diesel::SelectableHelper;
+
+        // the bounds rule out most rows before distances are compared
+        let (min_x, min_y, max_x, max_y) = shape.bounds();
+        let mut query = diesel::QueryDsl::into_boxed(dsl::characters
+            .select(ProfileSelect::as_select())
+            .filter(dsl::x.between(min_x, max_x))
+            .filter(dsl::y.between(min_y, max_y))
+            .filter(dsl::id.gt(after.unwrap_or(i32::MIN)))
+            .filter(dsl::id.ne_all(excluded))
+            .filter(dsl::id.ne_all(pending_deletions(&mut conn)?)));
+
+        if let Shape::Circle { x, y, radius } = shape {
+            query = query.filter(((dsl::x - x) * (dsl::x - x) + (dsl::y - y) * (dsl::y - y))
+                .le(radius * radius));
+        }
+
+        query
+            .order(dsl::id.asc())
+            .limit(limit)
+            .get_results(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn fetch_presences(
+    database: &Database,
+    character_ids: Vec<i32>,
+) -> diesel::QueryResult<Vec<PresenceSelect>> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::character_presence::dsl;
 
-        diesel::update(dsl::characters
-            .filter(dsl::id.eq(character_id)))
-            .set((dsl::x.eq(x),dsl::y.eq(y)))
+        dsl::character_presence
+            .filter(dsl::character_id.eq_any(character_ids))
+            .get_results(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn fetch_stats(
+    database: &Database,
+    character_id: i32,
+) -> diesel::QueryResult<StatsSelect> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::character_stats::dsl;
+
+        // characters get default stats the first time they're needed
+        diesel::insert_into(dsl::character_stats)
+            .values(dsl::character_id.eq(character_id))
+            .on_conflict_do_nothing()
+            .execute(&mut conn)?;
+
+        dsl::character_stats
+            .filter(dsl::character_id.eq(character_id))
+            .get_result(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn update_health(
+    database: &Database,
+    character_id: i32,
+    health: i32,
+) -> diesel::QueryResult<usize> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::character_stats::dsl;
+
+        diesel::update(dsl::character_stats
+            .filter(dsl::character_id.eq(character_id)))
+            .set((dsl::health.eq(health), dsl::modified.eq(diesel::dsl::now)))
             .execute(&mut conn)
-            .unwrap();
     })
     .await
-    .unwrap() 
+    .unwrap()
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::test_utils;
+pub async fn record_kill(
+    database: &Database,
+    killer_id: i32,
+    victim_id: i32,
+) -> diesel::QueryResult<()> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::character_stats::dsl;
 
-    use super::*;
+        conn.transaction(|conn| {
+            diesel::update(dsl::character_stats
+                .filter(dsl::character_id.eq(killer_id)))
+                .set(dsl::kills.eq(dsl::kills + 1))
+                .execute(conn)?;
 
-    #[actix_web::test]
-    async fn test_create_character() {
-        let database = "test_create_account";
-        test_utils::setup(database).await; 
-        let pool = test_utils::pool(database).await;
+            // the victim respawns with full health
+            diesel::update(dsl::character_stats
+                .filter(dsl::character_id.eq(victim_id)))
+                .set((
+                    dsl::deaths.eq(dsl::deaths + 1),
+                    dsl::health.eq(dsl::max_health),
+                    dsl::modified.eq(diesel::dsl::now)
+                ))
+                .execute(conn)?;
 
-        let result = create_character(&pool, "TEST", "PASSWORD").await;
-        assert!(result.is_ok());
+            Ok(())
+        })
+    })
+    .await
+    .unwrap()
+}
 
-        let record = result.unwrap();
-        assert_eq!(record.username, "TEST");
-        assert_eq!(record.password, "PASSWORD");
+pub async fn fetch_presence(
+    database: &Database,
+    character_id: i32,
+) -> diesel::QueryResult<Option<PresenceSelect>> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::character_presence::dsl;
 
-        test_utils::teardown(database);
-    }
+        dsl::character_presence
+            .filter(dsl::character_id.eq(character_id))
+            .get_result(&mut conn)
+            .optional()
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn start_session(
+    database: &Database,
+    character_id: i32,
+) -> diesel::QueryResult<()> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::character_presence::dsl as presence;
+        use crate::schema::character_sessions::dsl as sessions;
+
+        let now = Utc::now();
+
+        conn.transaction(|conn| {
+            diesel::insert_into(sessions::character_sessions)
+                .values((sessions::character_id.eq(character_id), sessions::started.eq(now)))
+                .execute(conn)?;
+
+            diesel::insert_into(presence::character_presence)
+                .values((
+                    presence::character_id.eq(character_id),
+                    presence::online.eq(true),
+                    presence::last_seen.eq(now)
+                ))
+                .on_conflict(presence::character_id)
+                .do_update()
+                .set((presence::online.eq(true), presence::last_seen.eq(now)))
+                .execute(conn)?;
+
+            Ok(())
+        })
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn end_session(
+    database: &Database,
+    character_id: i32,
+) -> diesel::QueryResult<()> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::character_presence::dsl as presence;
+        use crate::schema::character_sessions::dsl as sessions;
+
+        let now = Utc::now();
+
+        conn.transaction(|conn| {
+            // close any open sessions and add them to the total play time
+            let started = diesel::update(sessions::character_sessions
+                .filter(sessions::character_id.eq(character_id))
+                .filter(sessions::ended.is_null()))
+                .set(sessions::ended.eq(now))
+                .returning(sessions::started)
+                .get_results::<DateTime<Utc>>(conn)?;
+
+            let play_time = started
+                .iter()
+                .map(|s| (now - *s).num_seconds().max(0))
+                .sum::<i64>();
+
+            diesel::update(presence::character_presence
+                .filter(presence::character_id.eq(character_id)))
+                .set((
+                    presence::online.eq(false),
+                    presence::last_seen.eq(now),
+                    presence::play_time.eq(presence::play_time + play_time)
+                ))
+                .execute(conn)?;
+
+            Ok(())
+        })
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn reset_presence(database: &Database) -> diesel::QueryResult<usize> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::character_presence::dsl as presence;
+        use crate::schema::character_sessions::dsl as sessions;
+
+        conn.transaction(|conn| {
+            // sessions left open by a crash end when they started, since
+            // there's no way to know how long they really lasted.
+            diesel::update(sessions::character_sessions
+                .filter(sessions::ended.is_null()))
+                .set(sessions::ended.eq(sessions::started.nullable()))
+                .execute(conn)?;
+
+            diesel::update(presence::character_presence
+                .filter(presence::online.eq(true)))
+                .set(presence::online.eq(false))
+                .execute(conn)
+        })
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn insert_chat(
+    database: &Database,
+    message: ChatInsert,
+) -> diesel::QueryResult<ChatSelect> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::chat_messages::dsl;
 
+        diesel::insert_into(dsl::chat_messages)
+            .values(message)
+            .get_result(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+// fetch the newest messages on a channel, optionally only those older
+// than a given message id so that history can be paged backwards.
+pub async fn fetch_chat<T: ToString>(
+    database: &Database,
+    channel: T,
+    before: Option<i64>,
+    limit: i64,
+) -> diesel::QueryResult<Vec<ChatSelect>> {
+    let channel = channel.to_string();
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::chat_messages::dsl;
+        use diesel::query_dsl::methods::{LimitDsl, OrderDsl};
+
+        dsl::chat_messages
+            .filter(dsl::channel.eq(channel))
+            .filter(dsl::id.lt(before.unwrap_or(i64::MAX)))
+            .order(dsl::id.desc())
+            .limit(limit)
+            .get_results(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn fetch_mute(
+    database: &Database,
+    character_id: i32,
+) -> diesel::QueryResult<Option<MuteSelect>> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::account_mutes::dsl;
+
+        dsl::account_mutes
+            .filter(dsl::character_id.eq(character_id))
+            .get_result(&mut conn)
+            .optional()
+    })
+    .await
+    .unwrap()
+}
+
+// mute a character, replacing any existing mute. a mute without an
+// end time lasts until it's removed.
+pub async fn set_mute<T: ToString>(
+    database: &Database,
+    character_id: i32,
+    reason: T,
+    until: Option<DateTime<Utc>>,
+) -> diesel::QueryResult<MuteSelect> {
+    let reason = reason.to_string();
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::account_mutes::dsl;
+
+        diesel::insert_into(dsl::account_mutes)
+            .values((
+                dsl::character_id.eq(character_id),
+                dsl::reason.eq(&reason),
+                dsl::until.eq(until),
+            ))
+            .on_conflict(dsl::character_id)
+            .do_update()
+            .set((
+                dsl::reason.eq(&reason),
+                dsl::until.eq(until),
+                dsl::created.eq(Utc::now()),
+            ))
+            .get_result(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn remove_mute(
+    database: &Database,
+    character_id: i32,
+) -> diesel::QueryResult<usize> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::account_mutes::dsl;
+
+        diesel::delete(dsl::account_mutes
+            .filter(dsl::character_id.eq(character_id)))
+            .execute(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+// record a failed login for auditing
+pub async fn insert_login_attempt<T: ToString>(
+    database: &Database,
+    username: T,
+    address: Option<String>,
+    character_id: Option<i32>,
+    reason: &'static str,
+) -> diesel::QueryResult<LoginAttemptSelect> {
+    let username = username.to_string();
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::login_attempts::dsl;
+
+        diesel::insert_into(dsl::login_attempts)
+            .values((
+                dsl::username.eq(username),
+                dsl::address.eq(address),
+                dsl::character_id.eq(character_id),
+                dsl::reason.eq(reason),
+            ))
+            .get_result(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn fetch_login_attempts<T: ToString>(
+    database: &Database,
+    username: T,
+) -> diesel::QueryResult<Vec<LoginAttemptSelect>> {
+    let username = username.to_string();
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::login_attempts::dsl;
+        use diesel::query_dsl::methods::OrderDsl;
+
+        dsl::login_attempts
+            .filter(dsl::username.eq(username))
+            .order(dsl::id.asc())
+            .get_results(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn update_password<T: ToString>(
+    database: &Database,
+    character_id: i32,
+    password: T,
+) -> diesel::QueryResult<usize> {
+    let password = password.to_string();
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use tinker_records::schema::characters::dsl;
+
+        diesel::update(dsl::characters.filter(dsl::id.eq(character_id)))
+            .set(dsl::password.eq(password))
+            .execute(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn create_session(
+    database: &Database,
+    character_id: i32,
+    device: Option<String>,
+    address: Option<String>,
+) -> diesel::QueryResult<SessionSelect> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::login_sessions::dsl;
+
+        diesel::insert_into(dsl::login_sessions)
+            .values((
+                dsl::character_id.eq(character_id),
+                dsl::device.eq(device),
+                dsl::address.eq(address),
+            ))
+            .get_result(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+// record that a session was used, at most once a minute so that every
+// request doesn't have to write
+pub async fn touch_session(
+    database: &Database,
+    session_id: i64,
+) -> diesel::QueryResult<usize> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::login_sessions::dsl;
+
+        let now = Utc::now();
+        diesel::update(dsl::login_sessions
+            .filter(dsl::id.eq(session_id))
+            .filter(dsl::last_used.lt(now - chrono::Duration::minutes(1))))
+            .set(dsl::last_used.eq(now))
+            .execute(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+// the sessions of a character that haven't been revoked, most recently
+// used first
+pub async fn fetch_active_sessions(
+    database: &Database,
+    character_id: i32,
+) -> diesel::QueryResult<Vec<SessionSelect>> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::login_sessions::dsl;
+        use diesel::query_dsl::methods::OrderDsl;
+
+        dsl::login_sessions
+            .filter(dsl::character_id.eq(character_id))
+            .filter(dsl::revoked.is_null())
+            .order(dsl::last_used.desc())
+            .get_results(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+// revoke one session of a character, returning 0 if it isn't theirs or
+// was already revoked
+pub async fn revoke_session(
+    database: &Database,
+    character_id: i32,
+    session_id: i64,
+) -> diesel::QueryResult<usize> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::login_sessions::dsl;
+
+        diesel::update(dsl::login_sessions
+            .filter(dsl::id.eq(session_id))
+            .filter(dsl::character_id.eq(character_id))
+            .filter(dsl::revoked.is_null()))
+            .set(dsl::revoked.eq(Utc::now()))
+            .execute(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn fetch_session(
+    database: &Database,
+    session_id: i64,
+) -> diesel::QueryResult<Option<SessionSelect>> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::login_sessions::dsl;
+
+        dsl::login_sessions
+            .filter(dsl::id.eq(session_id))
+            .get_result(&mut conn)
+            .optional()
+    })
+    .await
+    .unwrap()
+}
+
+// revoke every session of a character that hasn't already been revoked,
+// except for the one given (usually the session making the change)
+pub async fn revoke_sessions(
+    database: &Database,
+    character_id: i32,
+    except: Option<i64>,
+) -> diesel::QueryResult<usize> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::login_sessions::dsl;
+
+        diesel::update(dsl::login_sessions
+            .filter(dsl::character_id.eq(character_id))
+            .filter(dsl::id.ne(except.unwrap_or(-1)))
+            .filter(dsl::revoked.is_null()))
+            .set(dsl::revoked.eq(Utc::now()))
+            .execute(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn create_reset<T: ToString>(
+    database: &Database,
+    character_id: i32,
+    token_hash: T,
+    expires: DateTime<Utc>,
+) -> diesel::QueryResult<ResetSelect> {
+    let token_hash = token_hash.to_string();
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::password_resets::dsl;
+
+        diesel::insert_into(dsl::password_resets)
+            .values((
+                dsl::character_id.eq(character_id),
+                dsl::token_hash.eq(token_hash),
+                dsl::expires.eq(expires),
+            ))
+            .get_result(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn fetch_reset(
+    database: &Database,
+    reset_id: i32,
+) -> diesel::QueryResult<Option<ResetSelect>> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::password_resets::dsl;
+
+        dsl::password_resets
+            .filter(dsl::id.eq(reset_id))
+            .get_result(&mut conn)
+            .optional()
+    })
+    .await
+    .unwrap()
+}
+
+// mark a reset as used, returning 0 if it already was so that a token
+// can't be used twice by racing requests
+pub async fn use_reset(
+    database: &Database,
+    reset_id: i32,
+) -> diesel::QueryResult<usize> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::password_resets::dsl;
+
+        diesel::update(dsl::password_resets
+            .filter(dsl::id.eq(reset_id))
+            .filter(dsl::used.is_null()))
+            .set(dsl::used.eq(Utc::now()))
+            .execute(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+// make every outstanding reset for a character unusable, once its
+// password has changed some other way
+pub async fn expire_resets(
+    database: &Database,
+    character_id: i32,
+) -> diesel::QueryResult<usize> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::password_resets::dsl;
+
+        diesel::update(dsl::password_resets
+            .filter(dsl::character_id.eq(character_id))
+            .filter(dsl::used.is_null()))
+            .set(dsl::used.eq(Utc::now()))
+            .execute(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+// every message a character sent, oldest first
+pub async fn fetch_sent_chat(
+    database: &Database,
+    character_id: i32,
+) -> diesel::QueryResult<Vec<ChatSelect>> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::chat_messages::dsl;
+        use diesel::query_dsl::methods::OrderDsl;
+
+        dsl::chat_messages
+            .filter(dsl::sender_id.eq(character_id))
+            .order(dsl::id.asc())
+            .get_results(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn fetch_sessions(
+    database: &Database,
+    character_id: i32,
+) -> diesel::QueryResult<Vec<SessionSelect>> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::login_sessions::dsl;
+        use diesel::query_dsl::methods::OrderDsl;
+
+        dsl::login_sessions
+            .filter(dsl::character_id.eq(character_id))
+            .order(dsl::id.asc())
+            .get_results(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn request_deletion(
+    database: &Database,
+    character_id: i32,
+    purge_after: DateTime<Utc>,
+) -> diesel::QueryResult<DeletionSelect> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::account_deletions::dsl;
+
+        diesel::insert_into(dsl::account_deletions)
+            .values((
+                dsl::character_id.eq(character_id),
+                dsl::purge_after.eq(purge_after),
+            ))
+            .on_conflict(dsl::character_id)
+            .do_update()
+            .set((
+                dsl::requested.eq(Utc::now()),
+                dsl::purge_after.eq(purge_after),
+            ))
+            .get_result(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn fetch_deletion(
+    database: &Database,
+    character_id: i32,
+) -> diesel::QueryResult<Option<DeletionSelect>> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::account_deletions::dsl;
+
+        dsl::account_deletions
+            .filter(dsl::character_id.eq(character_id))
+            .get_result(&mut conn)
+            .optional()
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn cancel_deletion(
+    database: &Database,
+    character_id: i32,
+) -> diesel::QueryResult<usize> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::account_deletions::dsl;
+
+        diesel::delete(dsl::account_deletions
+            .filter(dsl::character_id.eq(character_id)))
+            .execute(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+// delete every character whose grace period has passed, along with
+// everything that references it, returning the purged ids
+pub async fn purge_deleted(database: &Database) -> diesel::QueryResult<Vec<i32>> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::account_deletions::dsl as deletions;
+        use crate::schema::login_attempts::dsl as attempts;
+        use diesel::query_dsl::methods::SelectDsl;
+        use diesel::BoolExpressionMethods;
+        use tinker_records::schema::characters::dsl;
+
+        conn.transaction(|conn| {
+            let ids: Vec<i32> = deletions::account_deletions
+                .filter(deletions::purge_after.le(Utc::now()))
+                .select(deletions::character_id)
+                .get_results(conn)?;
+
+            let names: Vec<String> = dsl::characters
+                .filter(dsl::id.eq_any(&ids))
+                .select(dsl::username)
+                .get_results(conn)?;
+
+            // failed logins only lose their character when it's deleted,
+            // and would still record the name
+            diesel::delete(attempts::login_attempts.filter(
+                attempts::character_id.eq_any(&ids).or(attempts::username.eq_any(&names))
+            ))
+            .execute(conn)?;
+
+            diesel::delete(dsl::characters.filter(dsl::id.eq_any(&ids)))
+                .execute(conn)?;
+
+            Ok(ids)
+        })
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn fetch_totp(
+    database: &Database,
+    character_id: i32,
+) -> diesel::QueryResult<Option<TotpSelect>> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::account_totp::dsl;
+
+        dsl::account_totp
+            .filter(dsl::character_id.eq(character_id))
+            .get_result(&mut conn)
+            .optional()
+    })
+    .await
+    .unwrap()
+}
+
+// start (or restart) totp setup with a new secret, which isn't used
+// until it's enabled
+pub async fn set_totp_secret<T: ToString>(
+    database: &Database,
+    character_id: i32,
+    secret: T,
+) -> diesel::QueryResult<TotpSelect> {
+    let secret = secret.to_string();
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::account_totp::dsl;
+
+        diesel::insert_into(dsl::account_totp)
+            .values((dsl::character_id.eq(character_id), dsl::secret.eq(&secret)))
+            .on_conflict(dsl::character_id)
+            .do_update()
+            .set((
+                dsl::secret.eq(&secret),
+                dsl::created.eq(Utc::now()),
+                dsl::enabled.eq(None::<DateTime<Utc>>),
+                dsl::last_step.eq(None::<i64>),
+            ))
+            .get_result(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn enable_totp(
+    database: &Database,
+    character_id: i32,
+) -> diesel::QueryResult<usize> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::account_totp::dsl;
+
+        diesel::update(dsl::account_totp.filter(dsl::character_id.eq(character_id)))
+            .set(dsl::enabled.eq(Utc::now()))
+            .execute(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+// record the time step a code was accepted for, returning 0 if that
+// step (or a later one) was already used so a code can't be replayed
+pub async fn use_totp_step(
+    database: &Database,
+    character_id: i32,
+    step: i64,
+) -> diesel::QueryResult<usize> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::account_totp::dsl;
+        use diesel::BoolExpressionMethods;
+
+        diesel::update(dsl::account_totp
+            .filter(dsl::character_id.eq(character_id))
+            .filter(dsl::last_step.is_null().or(dsl::last_step.lt(step))))
+            .set(dsl::last_step.eq(step))
+            .execute(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+// turn off two factor for a character, forgetting its recovery codes
+pub async fn remove_totp(
+    database: &Database,
+    character_id: i32,
+) -> diesel::QueryResult<usize> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::account_totp::dsl;
+        use crate::schema::recovery_codes::dsl as codes;
+
+        conn.transaction(|conn| {
+            diesel::delete(codes::recovery_codes
+                .filter(codes::character_id.eq(character_id)))
+                .execute(conn)?;
+
+            diesel::delete(dsl::account_totp
+                .filter(dsl::character_id.eq(character_id)))
+                .execute(conn)
+        })
+    })
+    .await
+    .unwrap()
+}
+
+// replace every recovery code of a character with new ones
+pub async fn replace_recovery_codes(
+    database: &Database,
+    character_id: i32,
+    code_hashes: Vec<String>,
+) -> diesel::QueryResult<usize> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::recovery_codes::dsl;
+
+        conn.transaction(|conn| {
+            diesel::delete(dsl::recovery_codes
+                .filter(dsl::character_id.eq(character_id)))
+                .execute(conn)?;
+
+            let values = code_hashes
+                .into_iter()
+                .map(|h| (dsl::character_id.eq(character_id), dsl::code_hash.eq(h)))
+                .collect::<Vec<_>>();
+
+            diesel::insert_into(dsl::recovery_codes)
+                .values(values)
+                .execute(conn)
+        })
+    })
+    .await
+    .unwrap()
+}
+
+// the recovery codes of a character that haven't been used
+pub async fn fetch_recovery_codes(
+    database: &Database,
+    character_id: i32,
+) -> diesel::QueryResult<Vec<RecoveryCodeSelect>> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::recovery_codes::dsl;
+
+        dsl::recovery_codes
+            .filter(dsl::character_id.eq(character_id))
+            .filter(dsl::used.is_null())
+            .get_results(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+// mark a recovery code as used, returning 0 if it already was
+pub async fn use_recovery_code(
+    database: &Database,
+    code_id: i32,
+) -> diesel::QueryResult<usize> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::recovery_codes::dsl;
+
+        diesel::update(dsl::recovery_codes
+            .filter(dsl::id.eq(code_id))
+            .filter(dsl::used.is_null()))
+            .set(dsl::used.eq(Utc::now()))
+            .execute(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+// designate a character as a bot, so it can be given api keys
+pub async fn create_bot(
+    database: &Database,
+    character_id: i32,
+    created_by: Option<i32>,
+) -> diesel::QueryResult<BotSelect> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::bot_characters::dsl;
+
+        diesel::insert_into(dsl::bot_characters)
+            .values((
+                dsl::character_id.eq(character_id),
+                dsl::created_by.eq(created_by),
+            ))
+            .get_result(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn fetch_bot(
+    database: &Database,
+    character_id: i32,
+) -> diesel::QueryResult<Option<BotSelect>> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::bot_characters::dsl;
+
+        dsl::bot_characters
+            .filter(dsl::character_id.eq(character_id))
+            .get_result(&mut conn)
+            .optional()
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn fetch_bots(database: &Database) -> diesel::QueryResult<Vec<BotSelect>> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::bot_characters::dsl;
+        use diesel::query_dsl::methods::OrderDsl;
+
+        dsl::bot_characters
+            .order(dsl::character_id.asc())
+            .get_results(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn create_api_key<T: ToString>(
+    database: &Database,
+    character_id: i32,
+    name: T,
+    key_hash: T,
+    scopes: T,
+    created_by: Option<i32>,
+) -> diesel::QueryResult<ApiKeySelect> {
+    let name = name.to_string();
+    let key_hash = key_hash.to_string();
+    let scopes = scopes.to_string();
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::api_keys::dsl;
+
+        diesel::insert_into(dsl::api_keys)
+            .values((
+                dsl::character_id.eq(character_id),
+                dsl::name.eq(name),
+                dsl::key_hash.eq(key_hash),
+                dsl::scopes.eq(scopes),
+                dsl::created_by.eq(created_by),
+            ))
+            .get_result(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn fetch_api_key(
+    database: &Database,
+    key_id: i32,
+) -> diesel::QueryResult<Option<ApiKeySelect>> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::api_keys::dsl;
+
+        dsl::api_keys
+            .filter(dsl::id.eq(key_id))
+            .get_result(&mut conn)
+            .optional()
+    })
+    .await
+    .unwrap()
+}
+
+// every key of a bot, including revoked ones
+pub async fn fetch_api_keys(
+    database: &Database,
+    character_id: i32,
+) -> diesel::QueryResult<Vec<ApiKeySelect>> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::api_keys::dsl;
+        use diesel::query_dsl::methods::OrderDsl;
+
+        dsl::api_keys
+            .filter(dsl::character_id.eq(character_id))
+            .order(dsl::id.asc())
+            .get_results(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+// record that a key was used, at most once a minute like sessions
+pub async fn touch_api_key(
+    database: &Database,
+    key_id: i32,
+) -> diesel::QueryResult<usize> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::api_keys::dsl;
+        use diesel::BoolExpressionMethods;
+
+        let now = Utc::now();
+        diesel::update(dsl::api_keys
+            .filter(dsl::id.eq(key_id))
+            .filter(dsl::last_used.is_null().or(dsl::last_used.lt(now - chrono::Duration::minutes(1)))))
+            .set(dsl::last_used.eq(now))
+            .execute(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+// revoke a key, returning 0 if it doesn't exist or already was
+pub async fn revoke_api_key(
+    database: &Database,
+    key_id: i32,
+) -> diesel::QueryResult<usize> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::api_keys::dsl;
+
+        diesel::update(dsl::api_keys
+            .filter(dsl::id.eq(key_id))
+            .filter(dsl::revoked.is_null()))
+            .set(dsl::revoked.eq(Utc::now()))
+            .execute(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+// the role name stored for a character, if it isn't a player
+pub async fn fetch_role(
+    database: &Database,
+    character_id: i32,
+) -> diesel::QueryResult<Option<String>> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::account_roles::dsl;
+        use diesel::query_dsl::methods::SelectDsl;
+
+        dsl::account_roles
+            .filter(dsl::character_id.eq(character_id))
+            .select(dsl::role)
+            .get_result(&mut conn)
+            .optional()
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn set_role<T: ToString>(
+    database: &Database,
+    character_id: i32,
+    role: T,
+) -> diesel::QueryResult<usize> {
+    let role = role.to_string();
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::account_roles::dsl;
+
+        diesel::insert_into(dsl::account_roles)
+            .values((dsl::character_id.eq(character_id), dsl::role.eq(&role)))
+            .on_conflict(dsl::character_id)
+            .do_update()
+            .set((dsl::role.eq(&role), dsl::modified.eq(Utc::now())))
+            .execute(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn create_ban<T: ToString>(
+    database: &Database,
+    character_id: i32,
+    reason: T,
+    issued_by: Option<i32>,
+    until: Option<DateTime<Utc>>,
+) -> diesel::QueryResult<BanSelect> {
+    let reason = reason.to_string();
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::account_bans::dsl;
+
+        diesel::insert_into(dsl::account_bans)
+            .values((
+                dsl::character_id.eq(character_id),
+                dsl::reason.eq(reason),
+                dsl::issued_by.eq(issued_by),
+                dsl::until.eq(until),
+            ))
+            .get_result(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+// fetch the ban keeping a character out right now, if there is one.
+// permanent bans are preferred, then the one that ends last.
+pub async fn fetch_active_ban(
+    database: &Database,
+    character_id: i32,
+) -> diesel::QueryResult<Option<BanSelect>> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::account_bans::dsl;
+        use diesel::query_dsl::methods::OrderDsl;
+        use diesel::{BoolExpressionMethods, PgSortExpressionMethods};
+
+        dsl::account_bans
+            .filter(dsl::character_id.eq(character_id))
+            .filter(dsl::revoked.is_null())
+            .filter(dsl::until.is_null().or(dsl::until.gt(Utc::now())))
+            .order(dsl::until.desc().nulls_first())
+            .first(&mut conn)
+            .optional()
+    })
+    .await
+    .unwrap()
+}
+
+// revoke every ban on a character that hasn't already been revoked
+pub async fn revoke_bans(
+    database: &Database,
+    character_id: i32,
+) -> diesel::QueryResult<usize> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::account_bans::dsl;
+
+        diesel::update(dsl::account_bans
+            .filter(dsl::character_id.eq(character_id))
+            .filter(dsl::revoked.is_null()))
+            .set(dsl::revoked.eq(Utc::now()))
+            .execute(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+// create a report along with a snapshot of the messages it's about
+pub async fn create_report<T: ToString>(
+    database: &Database,
+    reporter_id: i32,
+    target_id: i32,
+    reason: T,
+    messages: Vec<(String, String, DateTime<Utc>)>,
+) -> diesel::QueryResult<ReportSelect> {
+    let reason = reason.to_string();
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::report_messages::dsl as snapshot;
+        use crate::schema::reports::dsl;
+
+        conn.transaction(|conn| {
+            let report: ReportSelect = diesel::insert_into(dsl::reports)
+                .values((
+                    dsl::reporter_id.eq(reporter_id),
+                    dsl::target_id.eq(target_id),
+                    dsl::reason.eq(reason),
+                ))
+                .get_result(conn)?;
+
+            let rows = messages
+                .into_iter()
+                .map(|(channel, text, sent)| (
+                    snapshot::report_id.eq(report.id),
+                    snapshot::channel.eq(channel),
+                    snapshot::text.eq(text),
+                    snapshot::sent.eq(sent),
+                ))
+                .collect::<Vec<_>>();
+
+            diesel::insert_into(snapshot::report_messages)
+                .values(rows)
+                .execute(conn)?;
+
+            Ok(report)
+        })
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn fetch_reports(
+    database: &Database,
+    resolved: bool,
+) -> diesel::QueryResult<Vec<ReportSelect>> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::reports::dsl;
+        use diesel::query_dsl::methods::OrderDsl;
+
+        dsl::reports
+            .filter(dsl::resolved.is_not_null().eq(resolved))
+            .order(dsl::id.asc())
+            .get_results(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn fetch_report(
+    database: &Database,
+    report_id: i32,
+) -> diesel::QueryResult<(ReportSelect, Vec<ReportMessageSelect>)> {
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::report_messages::dsl as snapshot;
+        use crate::schema::reports::dsl;
+        use diesel::query_dsl::methods::OrderDsl;
+
+        let report = dsl::reports
+            .filter(dsl::id.eq(report_id))
+            .get_result(&mut conn)?;
+
+        let messages = snapshot::report_messages
+            .filter(snapshot::report_id.eq(report_id))
+            .order(snapshot::sent.asc())
+            .get_results(&mut conn)?;
+
+        Ok((report, messages))
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn resolve_report<T: ToString>(
+    database: &Database,
+    report_id: i32,
+    resolution: T,
+) -> diesel::QueryResult<ReportSelect> {
+    let resolution = resolution.to_string();
+    let mut conn = database.get().expect("No database");
+    web::block(move || {
+        use crate::schema::reports::dsl;
+
+        diesel::update(dsl::reports.filter(dsl::id.eq(report_id)))
+            .set((
+                dsl::resolved.eq(Utc::now()),
+                dsl::resolution.eq(resolution),
+            ))
+            .get_result(&mut conn)
+    })
+    .await
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils;
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_create_character() {
+        let database = "test_create_account";
+        test_utils::setup(database).await; 
+        let pool = test_utils::pool(database).await;
+
+        let result = create_character(&pool, "TEST", "PASSWORD").await;
+        assert!(result.is_ok());
+
+        let record = result.unwrap();
+        assert_eq!(record.username, "TEST");
+        assert_eq!(record.password, "PASSWORD");
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_update_positions() {
+        let database = "test_update_positions";
+        test_utils::setup(database).await; 
+        let pool = test_utils::pool(database).await;
+
+        let first = fetch_character(&pool, "USERNAME").await.unwrap();
+        let second = create_character(&pool, "TEST", "PASSWORD").await.unwrap();
+
+        let result = update_positions(&pool, vec![
+            (first.id, 1.0, 2.0),
+            (second.id, 3.0, 4.0),
+        ]).await;
+        assert_eq!(result.unwrap(), 2);
+
+        let records = fetch_characters(&pool, vec![first.id, second.id]).await.unwrap();
+        for record in records {
+            match record.id == first.id {
+                true => assert_eq!((record.x, record.y), (1.0, 2.0)),
+                false => assert_eq!((record.x, record.y), (3.0, 4.0)),
+            }
+        }
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_session_presence() {
+        let database = "test_session_presence";
+        test_utils::setup(database).await; 
+        let pool = test_utils::pool(database).await;
+
+        let character = fetch_character(&pool, "USERNAME").await.unwrap();
+        assert_eq!(fetch_presence(&pool, character.id).await.unwrap(), None);
+
+        // connecting marks the character online
+        start_session(&pool, character.id).await.unwrap();
+        let presence = fetch_presence(&pool, character.id).await.unwrap().unwrap();
+        assert!(presence.online);
+        assert!(presence.last_seen.is_some());
+
+        // disconnecting marks the character offline
+        end_session(&pool, character.id).await.unwrap();
+        let presence = fetch_presence(&pool, character.id).await.unwrap().unwrap();
+        assert!(!presence.online);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_reset_presence() {
+        let database = "test_reset_presence";
+        test_utils::setup(database).await; 
+        let pool = test_utils::pool(database).await;
+
+        let character = fetch_character(&pool, "USERNAME").await.unwrap();
+        start_session(&pool, character.id).await.unwrap();
+
+        // a restart clears characters left online
+        assert_eq!(reset_presence(&pool).await.unwrap(), 1);
+        let presence = fetch_presence(&pool, character.id).await.unwrap().unwrap();
+        assert!(!presence.online);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_mute() {
+        let database = "test_mute";
+        test_utils::setup(database).await; 
+        let pool = test_utils::pool(database).await;
+
+        let character = fetch_character(&pool, "USERNAME").await.unwrap();
+        assert_eq!(fetch_mute(&pool, character.id).await.unwrap(), None);
+
+        // muting again replaces the first mute
+        set_mute(&pool, character.id, "SPAM", None).await.unwrap();
+        let until = Utc::now() + chrono::Duration::minutes(5);
+        set_mute(&pool, character.id, "ABUSE", Some(until)).await.unwrap();
+
+        let mute = fetch_mute(&pool, character.id).await.unwrap().unwrap();
+        assert_eq!(mute.reason, "ABUSE");
+        assert!(mute.active(Utc::now()));
+        assert!(!mute.active(until));
+
+        assert_eq!(remove_mute(&pool, character.id).await.unwrap(), 1);
+        assert_eq!(fetch_mute(&pool, character.id).await.unwrap(), None);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_reports() {
+        let database = "test_reports";
+        test_utils::setup(database).await; 
+        let pool = test_utils::pool(database).await;
+
+        let reporter = fetch_character(&pool, "USERNAME").await.unwrap();
+        let target = create_character(&pool, "TEST", "PASSWORD").await.unwrap();
+
+        let messages = vec![
+            ("global".to_string(), "first".to_string(), Utc::now()),
+            ("local".to_string(), "second".to_string(), Utc::now()),
+        ];
+        let report = create_report(&pool, reporter.id, target.id, "RUDE", messages).await.unwrap();
+
+        // the report is open until it's resolved
+        assert_eq!(fetch_reports(&pool, false).await.unwrap(), vec![report.clone()]);
+        assert!(fetch_reports(&pool, true).await.unwrap().is_empty());
+
+        let (_, snapshot) = fetch_report(&pool, report.id).await.unwrap();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot[0].text, "first");
+
+        let resolved = resolve_report(&pool, report.id, "WARNED").await.unwrap();
+        assert_eq!(resolved.resolution.as_deref(), Some("WARNED"));
+        assert!(fetch_reports(&pool, false).await.unwrap().is_empty());
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_bans() {
+        let database = "test_bans";
+        test_utils::setup(database).await; 
+        let pool = test_utils::pool(database).await;
+
+        let character = fetch_character(&pool, "USERNAME").await.unwrap();
+        assert_eq!(fetch_active_ban(&pool, character.id).await.unwrap(), None);
+
+        // expired bans don't count
+        let past = Utc::now() - chrono::Duration::minutes(5);
+        create_ban(&pool, character.id, "OLD", None, Some(past)).await.unwrap();
+        assert_eq!(fetch_active_ban(&pool, character.id).await.unwrap(), None);
+
+        // a permanent ban outlasts a timed one
+        let future = Utc::now() + chrono::Duration::minutes(5);
+        create_ban(&pool, character.id, "TIMED", None, Some(future)).await.unwrap();
+        create_ban(&pool, character.id, "FOREVER", None, None).await.unwrap();
+        let ban = fetch_active_ban(&pool, character.id).await.unwrap().unwrap();
+        assert_eq!(ban.reason, "FOREVER");
+
+        assert_eq!(revoke_bans(&pool, character.id).await.unwrap(), 3);
+        assert_eq!(fetch_active_ban(&pool, character.id).await.unwrap(), None);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_sessions() {
+        let database = "test_sessions";
+        test_utils::setup(database).await; 
+        let pool = test_utils::pool(database).await;
+
+        let character = fetch_character(&pool, "USERNAME").await.unwrap();
+        let first = create_session(&pool, character.id, None, None).await.unwrap();
+        let second = create_session(&pool, character.id, Some("PHONE".into()), None).await.unwrap();
+
+        // the session making the change is kept
+        assert_eq!(revoke_sessions(&pool, character.id, Some(second.id)).await.unwrap(), 1);
+        assert!(fetch_session(&pool, first.id).await.unwrap().unwrap().revoked.is_some());
+        assert!(fetch_session(&pool, second.id).await.unwrap().unwrap().revoked.is_none());
+
+        assert_eq!(revoke_sessions(&pool, character.id, None).await.unwrap(), 1);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_resets() {
+        let database = "test_resets";
+        test_utils::setup(database).await; 
+        let pool = test_utils::pool(database).await;
+
+        let character = fetch_character(&pool, "USERNAME").await.unwrap();
+        let expires = Utc::now() + chrono::Duration::minutes(5);
+        let first = create_reset(&pool, character.id, "HASH", expires).await.unwrap();
+        let second = create_reset(&pool, character.id, "HASH", expires).await.unwrap();
+        assert!(first.usable(Utc::now()));
+
+        // a reset can only be used once
+        assert_eq!(use_reset(&pool, first.id).await.unwrap(), 1);
+        assert_eq!(use_reset(&pool, first.id).await.unwrap(), 0);
+
+        assert_eq!(expire_resets(&pool, character.id).await.unwrap(), 1);
+        let second = fetch_reset(&pool, second.id).await.unwrap().unwrap();
+        assert!(!second.usable(Utc::now()));
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_purge_deleted() {
+        let database = "test_purge_deleted";
+        test_utils::setup(database).await; 
+        let pool = test_utils::pool(database).await;
+
+        let kept = fetch_character(&pool, "USERNAME").await.unwrap();
+        let purged = create_character(&pool, "TEST", "PASSWORD").await.unwrap();
+
+        insert_login_attempt(&pool, "TEST", None, Some(purged.id), "password").await.unwrap();
+        insert_login_attempt(&pool, "TEST", None, None, "password").await.unwrap();
+        let report = create_report(&pool, kept.id, purged.id, "SPAM", vec![]).await.unwrap();
+
+        // only deletions past their grace period are purged
+        let future = Utc::now() + chrono::Duration::minutes(5);
+        let past = Utc::now() - chrono::Duration::minutes(5);
+        request_deletion(&pool, kept.id, future).await.unwrap();
+        request_deletion(&pool, purged.id, past).await.unwrap();
+
+        assert_eq!(purge_deleted(&pool).await.unwrap(), vec![purged.id]);
+        assert!(fetch_character_by_id(&pool, purged.id).await.is_err());
+        assert!(fetch_deletion(&pool, purged.id).await.unwrap().is_none());
+        assert!(fetch_deletion(&pool, kept.id).await.unwrap().is_some());
+
+        // failed logins for the account go with it, but reports about it stay
+        assert!(fetch_login_attempts(&pool, "TEST").await.unwrap().is_empty());
+        let (report, _) = fetch_report(&pool, report.id).await.unwrap();
+        assert_eq!(report.target_id, None);
+
+        test_utils::teardown(database);
+    }
 }
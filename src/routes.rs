@@ -1,16 +1,38 @@
 use std::collections::{HashMap, VecDeque};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use tinker_records::models::CharacterSelect;
 use crate::payloads::AccountKey;
 use tinker_records::messages::*;
-use crate::errors::Result;
+use crate::config::{DuplicateLogin, CONFIG};
+use crate::errors::{Error, Result};
+use crate::heartbeat::Heartbeat;
+use crate::keys::{self, KeyScope};
+use crate::limits::{Kind, Limiter, Verdict};
+use crate::protocol::{
+    Channel, Control, Encoding, ErrorCode, Event, Frame, Handshake, Opening, FEATURE_RESUME,
+};
+use crate::auth::{self, Connecting, Permitted, Reporting, Role};
+use crate::chat;
+use crate::combat;
+use crate::models::ProfileSelect;
+use crate::moderation;
+use crate::positions;
+use crate::throttle::{self, Key};
+use crate::totp;
 use crate::utilities;
+use crate::validation;
 use crate::{
-    payloads::{AccountInfo, Login, Register},
+    payloads::{
+        AccountInfo, Area, Challenge, CharacterPage, ChatPage, Connect, History, Login,
+        LoginChallenge, Page, Profile, Register, Report, VerifyLogin,
+    },
     queries::{self, Database},
 };
-use actix_web::{get, post, web, HttpRequest, Responder};
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use chrono::Utc;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use actix_ws::{CloseCode, CloseReason, MessageStream, ProtocolError, Session};
 use futures_util::lock::Mutex;
 use futures_util::StreamExt;
 use futures_util::stream;
@@ -19,6 +41,9 @@ use tokio::time::sleep;
 use uuid::Uuid;
 use validator::Validate;
 
+// the longest device label kept for a session
+const DEVICE_LENGTH: usize = 128;
+
 pub static INCOMING_QUEUE: Lazy<Mutex<VecDeque<Message>>> = Lazy::new(|| { Default::default() });
 pub static OUTGOING_QUEUE: Lazy<Mutex<VecDeque<Message>>> = Lazy::new(|| { Default::default() });
 pub static DATABASE_QUEUE: Lazy<Mutex<VecDeque<Message>>> = Lazy::new(|| { Default::default() });
@@ -26,6 +51,11 @@ pub static DATABASE_QUEUE: Lazy<Mutex<VecDeque<Message>>> = Lazy::new(|| { Defau
 // TODO: merge USERS and REGISTRY. They should both just use the account id.
 pub static REGISTRY: Lazy<Mutex<HashMap<i32,AccountInfo>>> = Lazy::new(|| { Default::default() });
 pub static VIEWED: Lazy<Mutex<HashMap<Uuid,Vec<i32>>>> = Lazy::new(|| { Default::default() });
+pub static LATENCY: Lazy<Mutex<HashMap<i32,Duration>>> = Lazy::new(|| { Default::default() });
+pub static LINGERING: Lazy<Mutex<HashMap<i32,Uuid>>> = Lazy::new(|| { Default::default() });
+pub static CONNECTIONS: Lazy<Mutex<HashMap<i32,Uuid>>> = Lazy::new(|| { Default::default() });
+pub static MAILBOX: Lazy<Mutex<HashMap<i32,VecDeque<Event>>>> = Lazy::new(|| { Default::default() });
+pub static KICKS: Lazy<Mutex<HashMap<i32,String>>> = Lazy::new(|| { Default::default() });
 
 async fn get_initial(pool: &Database, account: AccountInfo) -> Vec<CharacterSelect> {
     let connected = REGISTRY
@@ -34,7 +64,18 @@ async fn get_initial(pool: &Database, account: AccountInfo) -> Vec<CharacterSele
         .values()
         .map(|a| a.id)
         .collect::<Vec<i32>>();
-    queries::local_entities(pool, account.id, connected).await.unwrap_or_default()
+    let mut entities = queries::local_entities(pool, account.id, connected)
+        .await
+        .unwrap_or_default();
+
+    // positions in memory are newer than the ones in the database
+    for entity in entities.iter_mut() {
+        if let Some((x, y)) = positions::current(entity.id).await {
+            entity.x = x;
+            entity.y = y;
+        }
+    }
+    entities
 }
 
 pub async fn register_handler(account: AccountInfo) -> i32 {
@@ -46,12 +87,88 @@ pub async fn register_handler(account: AccountInfo) -> i32 {
 pub async fn unregister_handler(id: i32) {
     println!("{} UNREGISTERED",id);
     REGISTRY.lock().await.remove(&id);
+    LATENCY.lock().await.remove(&id);
+    CONNECTIONS.lock().await.remove(&id);
+    MAILBOX.lock().await.remove(&id);
+    KICKS.lock().await.remove(&id);
+    chat::leave_party(id).await;
+    chat::forget_invites(id).await;
+}
+
+// ask a handler to close its connection with the given reason. returns
+// false if the account isn't connected.
+pub async fn kick_handler<T: ToString>(account_id: i32, reason: T) -> bool {
+    if !registered_handler(account_id).await {
+        return false;
+    }
+    KICKS.lock().await.insert(account_id, close_reason(reason.to_string()));
+    true
+}
+
+// cut a reason down to what fits in a close frame, without splitting
+// a character in half
+fn close_reason(mut reason: String) -> String {
+    let mut end = reason.len().min(validation::CLOSE_REASON);
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+    reason.truncate(end);
+    reason
+}
+
+// claim an account for a new connection, returning `None` if the policy
+// rejects it, or whether an existing session was replaced if it doesn't.
+pub async fn claim_connection(
+    account_id: i32,
+    connection: Uuid,
+    resumed: bool,
+    policy: DuplicateLogin
+) -> Option<bool> {
+    let mut connections = CONNECTIONS.lock().await;
+    let lingering = LINGERING.lock().await.contains_key(&account_id);
+
+    // a lingering or resumed session has no live socket to conflict with
+    let live = connections.contains_key(&account_id) && !lingering && !resumed;
+    if live && policy == DuplicateLogin::Reject {
+        return None;
+    }
+
+    // a new login takes over a lingering session without waiting for it
+    LINGERING.lock().await.remove(&account_id);
+
+    let replaced = connections.insert(account_id, connection).is_some();
+    Some(replaced && !resumed)
+}
+
+// check if a connection is still the one that owns the account
+pub async fn current_connection(account_id: i32, connection: Uuid) -> bool {
+    CONNECTIONS.lock().await.get(&account_id) == Some(&connection)
+}
+
+// how a connected handler authenticated, e.g. its login session
+pub async fn handler_account(account_id: i32) -> Option<AccountInfo> {
+    REGISTRY.lock().await.get(&account_id).cloned()
 }
 
 pub async fn registered_handler(account_id: i32) -> bool {
     REGISTRY.lock().await.contains_key(&account_id)
 }
 
+// push a client message onto the incoming queue, waiting for space if
+// it's full so that fast clients are slowed to the rate it's processed.
+pub async fn push_incoming(message: Message) {
+    loop {
+        {
+            let mut queue = INCOMING_QUEUE.lock().await;
+            if queue.len() < CONFIG.incoming_capacity {
+                queue.push_back(message);
+                return;
+            }
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+}
+
 // mark a message as viewed by a particular handler
 pub async fn set_viewed(account_id: i32, message_id: Uuid) {
     VIEWED.lock().await
@@ -79,6 +196,26 @@ pub async fn all_viewed(message_id: Uuid) -> bool {
             .unwrap_or(false))
 }
 
+// queue an event for each of the given handlers that is registered
+pub async fn send_event(handler_ids: &[i32], event: Event) {
+    let registry = REGISTRY.lock().await;
+    let mut mailbox = MAILBOX.lock().await;
+    for id in handler_ids.iter().filter(|id| registry.contains_key(id)) {
+        mailbox
+            .entry(*id)
+            .or_default()
+            .push_back(event.clone());
+    }
+}
+
+// take all events waiting for a particular handler
+pub async fn read_events(handler_id: i32) -> Vec<Event> {
+    MAILBOX.lock().await
+        .get_mut(&handler_id)
+        .map(|events| events.drain(..).collect())
+        .unwrap_or_default()
+}
+
 // read all un-viewed messages for a particular handler (and set viewed)
 pub async fn read_messages(account_id: i32) -> Vec<Message> {
     stream::iter(OUTGOING_QUEUE.lock().await.iter())
@@ -94,35 +231,345 @@ pub async fn read_messages(account_id: i32) -> Vec<Message> {
         .await
 }
 
-#[get("/login")]
+// wait for the client's hello and reply with the negotiated version and
+// features, or return the reason to close the session if it's incompatible.
+// legacy clients don't send a hello, so a client that sends a message
+// first (or nothing at all) gets the oldest version and no welcome. its
+// message is returned so it can be handled like any other.
+async fn handshake(
+    session: &mut Session,
+    stream: &mut MessageStream,
+    encoding: Encoding
+) -> std::result::Result<(Handshake, Option<actix_ws::Message>), CloseReason> {
+    let rejected = |description: String| CloseReason {
+        code: CloseCode::Protocol,
+        description: Some(description)
+    };
+
+    let hello = loop {
+        let Ok(frame) = tokio::time::timeout(CONFIG.handshake_timeout, stream.next()).await else {
+            return Ok((Handshake::legacy(), None));
+        };
+
+        let (opening, message) = match frame {
+            Some(Ok(actix_ws::Message::Text(text))) => {
+                (Encoding::Json.decode_opening(text.as_bytes()), actix_ws::Message::Text(text))
+            },
+            Some(Ok(actix_ws::Message::Binary(data))) => {
+                (Encoding::MessagePack.decode_opening(&data), actix_ws::Message::Binary(data))
+            },
+            Some(Ok(actix_ws::Message::Ping(data))) => {
+                let _ = session.pong(&data).await;
+                continue;
+            },
+            Some(Ok(actix_ws::Message::Pong(_) | actix_ws::Message::Nop)) => continue,
+            _ => return Err(rejected("Connection closed during handshake".into()))
+        };
+
+        match opening {
+            Some(Opening::Hello(hello)) => break hello,
+            Some(Opening::Legacy) => return Ok((Handshake::legacy(), Some(message))),
+            None => return Err(rejected("Expected hello".into()))
+        }
+    };
+
+    let welcome = hello.negotiate().map_err(rejected)?;
+    let _ = encoding.send(session, &Control::Welcome(welcome.clone())).await;
+    Ok((welcome, None))
+}
+
+// the error to answer a frame with if the account can't send it.
+// clients can only move and attack, only for the account the socket
+// logged in as, and bots can only send what their key allows.
+fn refuse(account: &AccountInfo, frame: &Frame) -> Option<Control> {
+    let id = match frame {
+        Frame::Message(m) => Some(m.id()),
+        Frame::Request(_) => None
+    };
+
+    let (code, message) = match frame {
+        Frame::Message(m) if !matches!(m.value, Value::Move(_) | Value::Attack(_)) => {
+            (ErrorCode::ServerOnly, "Only the server can send this message")
+        },
+        Frame::Message(m) if m.header.account_id != account.id => {
+            (ErrorCode::WrongAccount, "Messages can only be sent for your own account")
+        },
+        _ if !account.allows(KeyScope::of(frame)) => {
+            (ErrorCode::NotPermitted, "Not allowed by this api key")
+        },
+        _ => return None
+    };
+
+    Some(Control::Error { code, message: message.into(), id })
+}
+
+// unregister a handler and broadcast that the character left
+async fn leave_handler(handler_id: i32, character: CharacterSelect) {
+    unregister_handler(handler_id).await;
+
+    let message = Message::Disconnect(handler_id, character);
+    set_viewed(handler_id, message.id()).await;
+
+    INCOMING_QUEUE.lock().await.push_back(message);
+}
+
+// unregister a handler, broadcast that the character left, and close the
+// session. a connection that was replaced by another login only closes,
+// since the handler belongs to the new one.
+async fn disconnect_handler(
+    handler_id: i32,
+    connection: Uuid,
+    character: CharacterSelect,
+    session: Session,
+    reason: Option<CloseReason>
+) {
+    if current_connection(handler_id, connection).await {
+        leave_handler(handler_id, character).await;
+    }
+
+    // close session
+    let _ = session.close(reason).await;
+}
+
+// keep a handler registered after its connection drops so that outgoing
+// messages are retained for it, and only leave if it isn't resumed before
+// the grace period ends. a connection that was replaced by another login
+// has nothing to linger for, so it only closes.
+async fn linger_handler(
+    handler_id: i32,
+    connection: Uuid,
+    token: Uuid,
+    character: CharacterSelect,
+    session: Session,
+    reason: Option<CloseReason>
+) {
+    if !current_connection(handler_id, connection).await {
+        let _ = session.close(reason).await;
+        return;
+    }
+
+    println!("{} LINGERING",handler_id);
+    LINGERING.lock().await.insert(handler_id, token);
+
+    // close session
+    let _ = session.close(reason).await;
+
+    actix_web::rt::spawn(async move {
+        sleep(CONFIG.resume_grace).await;
+
+        // a resumed session (or a newer linger) replaces the token
+        let expired = {
+            let mut lingering = LINGERING.lock().await;
+            if lingering.get(&handler_id) == Some(&token) {
+                lingering.remove(&handler_id);
+                true
+            } else {
+                false
+            }
+        };
+
+        if expired {
+            leave_handler(handler_id, character).await;
+        }
+    });
+}
+
+// resume a lingering handler if the token matches the one it was issued
+pub async fn resume_handler(handler_id: i32, token: Uuid) -> bool {
+    let mut lingering = LINGERING.lock().await;
+    if lingering.get(&handler_id) == Some(&token) {
+        println!("{} RESUMED",handler_id);
+        lingering.remove(&handler_id);
+        true
+    } else {
+        false
+    }
+}
+
+// the label shown for a session: a name the client chose for itself,
+// or failing that its user agent
+fn device(req: &HttpRequest) -> Option<String> {
+    ["X-Device-Name", "User-Agent"]
+        .iter()
+        .filter_map(|h| req.headers().get(*h))
+        .filter_map(|v| v.to_str().ok())
+        .map(str::trim)
+        .find(|v| !v.is_empty())
+        .map(|v| v.chars().take(DEVICE_LENGTH).collect())
+}
+
+// finish a login by starting a session and giving the client a token
+async fn issue_key(pool: &Database, account: CharacterSelect, req: &HttpRequest) -> Result<AccountKey> {
+    // logging in during the grace period restores a deleted account
+    if queries::cancel_deletion(pool, account.id).await? > 0 {
+        println!("{} RESTORED", account.id);
+    }
+
+    // the role is carried in the token for clients to read, but routes
+    // that need one look it up again
+    let role = auth::fetch_role(pool, account.id).await?;
+
+    // each login is a session that can be revoked on its own
+    let session = queries::create_session(
+        pool,
+        account.id,
+        device(req),
+        throttle::address(req),
+    ).await?;
+
+    // create an authentication token from the account
+    let token = utilities::token::encode(&AccountInfo {
+        id: account.id,
+        username: account.username.clone(),
+        role,
+        session: Some(session.id),
+        key: None,
+    })?;
+
+    Ok(AccountKey {
+        id: account.id,
+        name: account.username,
+        role,
+        token,
+    })
+}
+
+#[post("/login")]
 async fn login(
     pool: web::Data<Database>,
-    form: web::Json<Login>
+    form: web::Json<Login>,
+    req: HttpRequest
 ) -> Result<impl Responder> {
     // validate the form fields
     form.validate()?;
 
-    let username = form.username.clone();
+    // usernames are stored normalized, so lookalike input still matches
+    let username = validation::normalize(&form.username);
     let password = form.password.clone();
 
-    // fetch the database record by username
-    let account = queries::fetch_character(&pool, username).await?;
+    let now = Instant::now();
+    let address = throttle::address(&req);
+    let keys = throttle::keys(&username, &address);
 
-    // validate the password hash
-    utilities::password::valid(account.password, password)?;
+    // refuse locked out clients before doing any work
+    if let Some(wait) = throttle::locked(&keys, now).await {
+        let _ = queries::insert_login_attempt(&pool, &username, address, None, "locked").await;
+        return Err(Error::Locked(wait));
+    }
 
-    // create an authentication token from the account
-    let token = utilities::token::encode(&AccountInfo {
-        id: account.id,
-        username: account.username.clone()
-    })?;
+    // fetch the database record by username. names saved before they
+    // were normalized are still found by exactly what was typed.
+    let mut account = queries::fetch_character(&pool, &username).await;
+    if matches!(account, Err(diesel::result::Error::NotFound)) && form.username != username {
+        account = queries::fetch_character(&pool, &form.username).await;
+    }
+
+    let account = match account {
+        Ok(account) => Some(account),
+        Err(diesel::result::Error::NotFound) => None,
+        Err(error) => return Err(error.into())
+    };
+
+    // validate the password hash. unknown usernames are checked against
+    // a dummy hash so they can't be told apart by how long they take.
+    let valid = match &account {
+        Some(account) => utilities::password::valid(account.password.clone(), password.clone()).is_ok(),
+        None => {
+            utilities::password::dummy(&password);
+            false
+        }
+    };
+
+    if !valid {
+        let reason = match account {
+            Some(_) => "bad_password",
+            None => "unknown_username"
+        };
+        let id = account.as_ref().map(|a| a.id);
+        let _ = queries::insert_login_attempt(&pool, &username, address, id, reason).await;
+
+        throttle::fail(keys, now).await;
+        return Err(Error::InvalidCredentials);
+    }
+
+    let account = account.ok_or(Error::InvalidCredentials)?;
+
+    // the password is known now, so hashes with an old cost can be
+    // replaced without anyone having to reset their password
+    if utilities::password::outdated(&account.password) {
+        if let Ok(hash) = utilities::password::hash(&password) {
+            let _ = queries::update_password(&pool, account.id, hash).await;
+        }
+    }
+
+    // banned accounts are told why they can't log in
+    if let Err(error) = moderation::check_ban(&pool, account.id).await {
+        let _ = queries::insert_login_attempt(&pool, &username, address, Some(account.id), "banned").await;
+        return Err(error);
+    }
+
+    // accounts with two factor need a code before they get a token. the
+    // failures aren't forgotten until then, so codes can't be guessed by
+    // logging in again between guesses.
+    if totp::enabled(&pool, account.id).await?.is_some() {
+        let expires = Utc::now() + chrono::Duration::from_std(CONFIG.challenge_expiry)
+            .unwrap_or(chrono::Duration::minutes(5));
+        let challenge = utilities::token::encode(&LoginChallenge {
+            challenge_for: account.id,
+            expires,
+        })?;
+        return Ok(HttpResponse::Accepted().json(Challenge { challenge, expires }));
+    }
+
+    throttle::LOGINS.lock().await.succeed(&Key::Username(username));
 
     // return the account information
-    Ok(web::Json(AccountKey {
-        id: account.id,
-        name: account.username,
-        token: token, 
-    }))
+    Ok(HttpResponse::Ok().json(issue_key(&pool, account, &req).await?))
+}
+
+#[post("/login/verify")]
+async fn verify_login(
+    pool: web::Data<Database>,
+    form: web::Json<VerifyLogin>,
+    req: HttpRequest
+) -> Result<impl Responder> {
+    form.validate()?;
+
+    // the challenge is only good for a short time after the password
+    let challenge: LoginChallenge = utilities::token::decode(&form.challenge)
+        .map_err(|_| Error::Unauthorized)?;
+
+    if challenge.expires <= Utc::now() {
+        return Err(Error::Unauthorized);
+    }
+
+    let account = queries::fetch_character_by_id(&pool, challenge.challenge_for).await?;
+
+    // codes are throttled along with passwords
+    let now = Instant::now();
+    let address = throttle::address(&req);
+    let keys = throttle::keys(&account.username, &address);
+
+    if let Some(wait) = throttle::locked(&keys, now).await {
+        let _ = queries::insert_login_attempt(&pool, &account.username, address, Some(account.id), "locked").await;
+        return Err(Error::Locked(wait));
+    }
+
+    // two factor may have been turned off since the challenge
+    let Some(setup) = totp::enabled(&pool, account.id).await? else {
+        return Err(Error::Unauthorized);
+    };
+
+    if !totp::check(&pool, &setup, &form.code).await? {
+        let _ = queries::insert_login_attempt(&pool, &account.username, address, Some(account.id), "bad_code").await;
+        throttle::fail(keys, now).await;
+        return Err(Error::InvalidCode);
+    }
+
+    throttle::LOGINS.lock().await.succeed(&Key::Username(account.username.clone()));
+    moderation::check_ban(&pool, account.id).await?;
+
+    Ok(web::Json(issue_key(&pool, account, &req).await?))
 }
 
 #[post("/register")]
@@ -130,113 +577,528 @@ async fn register(
     pool: web::Data<Database>, 
     form: web::Json<Register>
 ) -> Result<impl Responder> {
+    let mut form = form.into_inner();
+    form.username = validation::normalize(&form.username);
 
     // validate the form fields
     form.validate()?;
+    validation::password("password1", &form.password1, &form.username)?;
 
     // get the username and hash the password
     let username = form.username.clone();
     let password = utilities::password::hash(form.password1.clone())?;
 
-    // create the database record
-    let account = queries::create_character(&pool, username, password).await?;
+    // create the database record. names are unique ignoring case.
+    let account = match queries::create_character(&pool, username, password).await {
+        Ok(account) => account,
+        Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            return Err(validation::taken().into())
+        }
+        Err(error) => return Err(error.into())
+    };
 
     // return the account information
     Ok(web::Json(AccountInfo {
         id: account.id, 
         username: account.username,
+        role: Role::Player,
+        session: None,
+        key: None,
     }))
 }
 
+// the public information of characters, with positions from memory
+// where they're newer than the database
+async fn profiles(pool: &Database, characters: Vec<ProfileSelect>) -> Result<Vec<Profile>> {
+    let ids = characters.iter().map(|c| c.id).collect();
+    let presences = queries::fetch_presences(pool, ids).await?;
+
+    let mut result = Vec::new();
+    for character in characters {
+        let presence = presences.iter().find(|p| p.character_id == character.id);
+        let (x, y) = positions::current(character.id)
+            .await
+            .unwrap_or((character.x, character.y));
+
+        result.push(Profile {
+            id: character.id,
+            username: character.username,
+            x,
+            y,
+            online: presence.map(|p| p.online).unwrap_or(false),
+            last_seen: presence.and_then(|p| p.last_seen),
+            play_time: presence.map(|p| p.play_time).unwrap_or(0),
+        });
+    }
+    Ok(result)
+}
+
+// a page of profiles, which is full if there might be another after it
+async fn page(pool: &Database, characters: Vec<ProfileSelect>, limit: i64) -> Result<CharacterPage> {
+    let next = match characters.len() as i64 == limit {
+        true => characters.last().map(|c| c.id),
+        false => None
+    };
+
+    Ok(CharacterPage {
+        characters: profiles(pool, characters).await?,
+        next,
+    })
+}
+
+#[get("/characters/{id:\\d+}")]
+async fn profile(
+    pool: web::Data<Database>,
+    id: web::Path<i32>
+) -> Result<impl Responder> {
+    let character = queries::fetch_profile(&pool, *id).await?;
+
+    // return the public character information
+    let record = profiles(&pool, vec![character]).await?.remove(0);
+    Ok(web::Json(record))
+}
+
+#[get("/characters/by-name/{username}")]
+async fn profile_by_name(
+    pool: web::Data<Database>,
+    username: web::Path<String>
+) -> Result<impl Responder> {
+    let username = validation::normalize(&username);
+    let character = queries::fetch_profile_by_name(&pool, username).await?;
+
+    let record = profiles(&pool, vec![character]).await?.remove(0);
+    Ok(web::Json(record))
+}
+
+#[get("/characters/online")]
+async fn online(
+    pool: web::Data<Database>,
+    query: web::Query<Page>
+) -> Result<impl Responder> {
+    query.validate()?;
+
+    let limit = query.limit.unwrap_or(50);
+    let after = query.after.unwrap_or(i32::MIN);
+
+    // the connected characters are paged through in order of id. hidden
+    // characters are left out first so they don't shorten the page.
+    let pending = queries::fetch_pending_deletions(&pool).await?;
+    let mut connected = REGISTRY
+        .lock()
+        .await
+        .keys()
+        .copied()
+        .filter(|id| *id > after && !pending.contains(id))
+        .collect::<Vec<i32>>();
+    connected.sort_unstable();
+    connected.truncate(limit as usize);
+
+    let characters = queries::fetch_profiles(&pool, connected).await?;
+    Ok(web::Json(page(&pool, characters, limit).await?))
+}
+
+/// Characters in a rectangle or circle. Characters that have moved
+/// since the server started are found by their position in memory, and
+/// everyone else by the position in the database.
+#[get("/world/characters")]
+async fn characters_within(
+    pool: web::Data<Database>,
+    query: web::Query<Area>
+) -> Result<impl Responder> {
+    query.validate()?;
+
+    let shape = query.shape().expect("Area was validated");
+    let limit = query.limit.unwrap_or(50);
+    let after = query.after.unwrap_or(i32::MIN);
+
+    // the saved positions of moved characters are out of date, so the
+    // database is only searched for the rest
+    let (tracked, inside) = positions::within(&shape).await;
+    let pending = queries::fetch_pending_deletions(&pool).await?;
+    let mut inside = inside
+        .into_iter()
+        .filter(|id| *id > after && !pending.contains(id))
+        .collect::<Vec<i32>>();
+    inside.sort_unstable();
+    inside.truncate(limit as usize);
+
+    let mut characters = queries::fetch_profiles_within(&pool, shape, query.after, tracked, limit).await?;
+    characters.extend(queries::fetch_profiles(&pool, inside).await?);
+    characters.sort_unstable_by_key(|c| c.id);
+    characters.truncate(limit as usize);
+
+    Ok(web::Json(page(&pool, characters, limit).await?))
+}
+
+#[get("/chat/global")]
+async fn chat_history(
+    pool: web::Data<Database>,
+    query: web::Query<History>
+) -> Result<impl Responder> {
+    query.validate()?;
+
+    // only global chat is public, other channels are never served
+    let limit = query.limit.unwrap_or(50);
+    let messages = queries::fetch_chat(&pool, Channel::Global.name(), query.before, limit).await?;
+
+    // the next page starts before the oldest message in this one
+    let next = match messages.len() as i64 == limit {
+        true => messages.last().map(|m| m.id),
+        false => None
+    };
+
+    Ok(web::Json(ChatPage { messages, next }))
+}
+
+#[post("/report")]
+async fn report_player(
+    pool: web::Data<Database>,
+    account: Permitted<Reporting>,
+    form: web::Json<Report>
+) -> Result<impl Responder> {
+    form.validate()?;
+
+    // players can't report themselves
+    if account.id == form.target {
+        return Err(Error::Forbidden);
+    }
+
+    let target = queries::fetch_character_by_id(&pool, form.target).await?;
+
+    // keep what the player said in case it's gone by the time it's reviewed
+    let messages = moderation::recent(target.id)
+        .await
+        .into_iter()
+        .map(|m| (m.channel, m.text, m.sent))
+        .collect();
+
+    let record = queries::create_report(&pool, account.id, target.id, &form.reason, messages).await?;
+    Ok(web::Json(record))
+}
+
 #[get("/connect/{token}")]
 pub async fn connect(
     pool: web::Data<Database>, 
     token: web::Path<String>,
+    query: web::Query<Connect>,
     req: HttpRequest,
     body: web::Payload,
 ) -> Result<impl Responder> {
-    
-    let (response, mut session, mut stream) = actix_ws::handle(&req, body)?;
 
-    actix_web::rt::spawn(async move {
+    // paths end up in access logs, so api keys are only accepted in
+    // the authorization header
+    if keys::is_key(&token) {
+        return Err(Error::Unauthorized);
+    }
 
-        // decode the login token to get basic account information
-        let account: AccountInfo = utilities::token::decode(token.to_string())
-            .expect("Could not decode token");
+    // decode the login token to get basic account information
+    let account: AccountInfo = utilities::token::decode(token.to_string())?;
 
-        let character: CharacterSelect = queries::fetch_character(
-            &pool, 
-            &account.username
-        ).await.expect("Could not find character");
+    // refuse logged out and banned accounts before upgrading so they get a reason
+    auth::check_session(&pool, &account).await?;
+    moderation::check_ban(&pool, account.id).await?;
+
+    open_socket(pool, query.into_inner(), &req, body, account)
+}
+
+/// Connect with the token or api key in the `Authorization` header
+/// rather than the path, for clients that can set headers (e.g. bots).
+#[get("/connect")]
+pub async fn connect_bearer(
+    pool: web::Data<Database>,
+    query: web::Query<Connect>,
+    req: HttpRequest,
+    body: web::Payload,
+    account: Permitted<Connecting>,
+) -> Result<impl Responder> {
+    open_socket(pool, query.into_inner(), &req, body, account.account)
+}
+
+// upgrade to a websocket for an account that has already been checked,
+// and run the session until it closes
+fn open_socket(
+    pool: web::Data<Database>,
+    query: Connect,
+    req: &HttpRequest,
+    body: web::Payload,
+    account: AccountInfo,
+) -> Result<HttpResponse> {
+    let (mut response, mut session, stream) = actix_ws::handle(req, body)?;
+
+    // frames over the limit are read as an overflow error
+    let mut stream = stream.max_frame_size(CONFIG.max_frame_size);
+
+    // use the encoding requested by the client, or fall back to json
+    let encoding = match Encoding::negotiate(req) {
+        Some(encoding) => {
+            encoding.accept(&mut response);
+            encoding
+        },
+        None => Encoding::default()
+    };
+
+    actix_web::rt::spawn(async move {
+
+        // the character can have been renamed or deleted since the token
+        // was issued, so it's found by id and may be missing
+        let Ok(character) = queries::fetch_character_by_id(&pool, account.id).await else {
+            let reason = CloseReason {
+                code: CloseCode::Policy,
+                description: Some("Character not found".into())
+            };
+            let _ = session.close(Some(reason)).await;
+            return;
+        };
+
+        // agree on a protocol version before sending anything else
+        let (welcome, mut pending) = match handshake(&mut session, &mut stream, encoding).await {
+            Ok(handshake) => handshake,
+            Err(reason) => {
+                let _ = session.close(Some(reason)).await;
+                return;
+            }
+        };
     
+        // a resumed handler is still registered, and any messages it
+        // missed are still waiting in the outgoing queue.
+        let resumed = match query.resume {
+            Some(token) if welcome.has(FEATURE_RESUME) => resume_handler(account.id, token).await,
+            _ => false
+        };
+
+        // make sure there is only one live session for the account
+        let connection = utilities::random_uuid();
+        let replaced = match claim_connection(
+            account.id,
+            connection,
+            resumed,
+            CONFIG.duplicate_login
+        ).await {
+            Some(replaced) => replaced,
+            None => {
+                let reason = CloseReason {
+                    code: CloseCode::Policy,
+                    description: Some("Already connected".into())
+                };
+                let _ = session.close(Some(reason)).await;
+                return;
+            }
+        };
+
         // the id for this particular connection
-        let handler_id = register_handler(account.clone()).await;
-
-        // get current records for entities that are-
-        //      - connected
-        //      - in range
-        let entities = get_initial(&pool,account.clone()).await;
-        
-        // build an "InitialState" message for the client
-        let item = Message::Initial(account.id,entities);
-
-        // send the initial state message to the client
-        if let Ok(data) = serde_json::to_string(&item) {
-            let _ = session.text(data).await.map_err(|_| {
+        let handler_id = match resumed {
+            true => account.id,
+            false => register_handler(account.clone()).await
+        };
+
+        if !resumed {
+            // get current records for entities that are-
+            //      - connected
+            //      - in range
+            let entities = get_initial(&pool,account.clone()).await;
+            
+            // build an "InitialState" message for the client
+            let item = Message::Initial(account.id,entities);
+
+            // send the initial state message to the client
+            let _ = encoding.send(&mut session, &item).await.map_err(|_| {
                 // TODO: log failure and maybe disconnect
             });
+
+            // a replaced session never left, so there's nothing to announce
+            if !replaced {
+                let message = Message::Connect(account.id,character.clone());
+                set_viewed(account.id, message.id()).await;
+
+                INCOMING_QUEUE.lock().await.push_back(message);
+            }
         }
 
-        let message = Message::Connect(account.id,character.clone());
-        set_viewed(account.id, message.id()).await;
+        // give the client a token it can use to resume this session
+        let resume = utilities::random_uuid();
+        if welcome.has(FEATURE_RESUME) {
+            let _ = encoding.send(&mut session, &Control::Resume { token: resume }).await;
+        }
 
-        INCOMING_QUEUE.lock().await.push_back(message);
+        let mut heartbeat = Heartbeat::new(
+            CONFIG.heartbeat_interval,
+            CONFIG.heartbeat_misses
+        );
+
+        let mut limiter = Limiter::new(
+            CONFIG.move_rate,
+            CONFIG.attack_rate,
+            CONFIG.chat_rate,
+            CONFIG.other_rate,
+            CONFIG.strike_rate,
+            Instant::now()
+        );
+
+        let mut malformed = 0;
 
         loop {
-            // create timeout and stream futures
-            let timeout = sleep(Duration::from_millis(100));
-            let source = stream.next();
-
-            // create a combined future that waits for either
-            // the next message or a timeout, whichever is sooner.
-            let result = tokio::select! {
-                message = source => message,
-                _ = timeout => None
+            // another login took over the account, so leave quietly
+            if !current_connection(handler_id, connection).await {
+                let reason = CloseReason {
+                    code: CloseCode::Policy,
+                    description: Some("Logged in elsewhere".into())
+                };
+                let _ = session.close(Some(reason)).await;
+                break;
+            }
+
+            // an admin removed the player, so they leave immediately
+            if let Some(description) = KICKS.lock().await.remove(&handler_id) {
+                let reason = CloseReason {
+                    code: CloseCode::Policy,
+                    description: Some(description)
+                };
+                disconnect_handler(handler_id, connection, character, session, Some(reason)).await;
+                break;
+            }
+
+            // a legacy client's first message was read during the handshake
+            let result = match pending.take() {
+                Some(message) => Some(Some(Ok(message))),
+                None => {
+                    // create timeout and stream futures
+                    let timeout = sleep(Duration::from_millis(100));
+                    let source = stream.next();
+
+                    // create a combined future that waits for either
+                    // the next message or a timeout, whichever is sooner.
+                    tokio::select! {
+                        message = source => Some(message),
+                        _ = timeout => None
+                    }
+                }
             };
 
-            // handle incoming messages
-            match result {
-                Some(Ok(actix_ws::Message::Text(text))) => {
-                    dbg!(&text);
-                    if let Ok(m) = Message::deserialize(text.as_bytes()) {
-                        // track incoming so we don't send them back
-                        set_viewed(account.id, m.id()).await;
-                        // enqueue for database insertion and response
-                        INCOMING_QUEUE.lock().await.push_back(m.clone());
+            // handle incoming messages. text frames are always json so
+            // that they can be written by hand while debugging.
+            let incoming = match result {
+                Some(Some(Ok(actix_ws::Message::Text(text)))) => {
+                    Some(Encoding::Json.decode_frame(text.as_bytes()))
+                },
+                Some(Some(Ok(actix_ws::Message::Binary(data)))) => {
+                    Some(Encoding::MessagePack.decode_frame(&data))
+                },
+                Some(Some(Ok(actix_ws::Message::Ping(data)))) => {
+                    let _ = session.pong(&data).await;
+                    None
+                },
+                Some(Some(Ok(actix_ws::Message::Pong(_)))) => {
+                    heartbeat.pong(Instant::now());
+                    if let Some(latency) = heartbeat.latency() {
+                        LATENCY.lock().await.insert(handler_id, latency);
                     }
-                }, 
+                    None
+                },
+                Some(Some(Ok(actix_ws::Message::Continuation(_) | actix_ws::Message::Nop))) => None,
+                Some(Some(Err(ProtocolError::Overflow))) => {
+                    let reason = CloseReason {
+                        code: CloseCode::Size,
+                        description: Some("Frame too large".into())
+                    };
+                    disconnect_handler(handler_id, connection, character, session, Some(reason)).await;
+                    break;
+                },
+                Some(Some(Ok(actix_ws::Message::Close(reason)))) => {
+                    // the client left on purpose, so don't wait for it
+                    disconnect_handler(handler_id, connection, character, session, reason).await;
+                    break;
+                },
                 Some(_) => {
-                    unregister_handler(handler_id).await;
-                    
-                    let message = Message::Disconnect(account.id, character);
-                    set_viewed(account.id, message.id()).await;
-                    
-                    INCOMING_QUEUE.lock().await.push_back(message);
-
-                    // close session
-                    let _ = session.close(None).await;
-                    break;                    
+                    // the connection was lost, so wait for the client to resume
+                    linger_handler(handler_id, connection, resume, character, session, None).await;
+                    break;
                 },
-                None => ()
+                None => None
+            };
+
+            // tell the client what was wrong with a frame it sent, and
+            // drop it if it keeps sending frames that can't be read
+            if let Some(Err(error)) = &incoming {
+                malformed += 1;
+
+                if malformed >= CONFIG.malformed_limit {
+                    let reason = CloseReason {
+                        code: CloseCode::Invalid,
+                        description: Some("Too many malformed frames".into())
+                    };
+                    disconnect_handler(handler_id, connection, character, session, Some(reason)).await;
+                    break;
+                }
+
+                let _ = encoding.send(&mut session, error).await;
+            }
+
+            if let Some(Ok(frame)) = incoming {
+                // frames the account isn't allowed to send are answered with
+                // an error instead of being handled, but still count against
+                // its limits
+                match (limiter.check(Kind::of(&frame), Instant::now()), refuse(&account, &frame)) {
+                    (Verdict::Accept, Some(error)) => {
+                        let _ = encoding.send(&mut session, &error).await;
+                    },
+                    (Verdict::Accept, None) => match frame {
+                        Frame::Message(m) => match combat::offline(&m).await {
+                            Some(error) => {
+                                let _ = encoding.send(&mut session, &error).await;
+                            },
+                            None => {
+                                // track incoming so we don't send them back
+                                set_viewed(account.id, m.id()).await;
+                                // enqueue for database insertion and response
+                                push_incoming(m).await;
+                            }
+                        },
+                        Frame::Request(request) => {
+                            // requests are answered directly rather than relayed
+                            if let Err(error) = chat::handle(&pool, &character, request).await {
+                                let _ = encoding.send(&mut session, &error).await;
+                            }
+                        }
+                    },
+                    (Verdict::Drop, _) => (),
+                    (Verdict::Disconnect, _) => {
+                        let reason = CloseReason {
+                            code: CloseCode::Policy,
+                            description: Some("Rate limit exceeded".into())
+                        };
+                        disconnect_handler(handler_id, connection, character, session, Some(reason)).await;
+                        break;
+                    }
+                }
+            }
+
+            // ping the client, and drop it if it stopped answering
+            let now = Instant::now();
+            if heartbeat.due(now) {
+                heartbeat.ping(now);
+
+                if heartbeat.expired() {
+                    let reason = CloseReason {
+                        code: CloseCode::Away,
+                        description: Some("Missed heartbeats".into())
+                    };
+                    linger_handler(handler_id, connection, resume, character, session, Some(reason)).await;
+                    break;
+                }
+
+                let _ = session.ping(b"").await;
             }
 
             // read all un-viewed messages (marking as viewed) and send them
             for item in read_messages(handler_id).await {
-                if let Ok(data) = serde_json::to_string(&item) {
-                    let _ = session.text(data).await.map_err(|_| {
-                        // TODO: log failure and maybe disconnect
-                    });
-                }
+                let _ = encoding.send(&mut session, &item).await.map_err(|_| {
+                    // TODO: log failure and maybe disconnect
+                });
+            }
+
+            // send any events generated for this handler
+            for event in read_events(handler_id).await {
+                let _ = encoding.send(&mut session, &event).await;
             }
 
         }
@@ -254,6 +1116,10 @@ mod tests {
     use diesel::pg::PgConnection;
     use diesel::r2d2::ConnectionManager;
     use futures_util::{SinkExt as _, StreamExt as _};
+    use actix_http::ws::{self, Frame};
+    use actix_web::http::StatusCode;
+    use argon2::password_hash::{rand_core::OsRng, PasswordHasher, SaltString};
+    use crate::protocol::{self, PROTOCOL_VERSION};
 
     mod query {
 
@@ -269,6 +1135,17 @@ mod tests {
                 )
                 .await
             };
+            ($app: ident, $path: expr, $record: expr, $token: expr) => {
+                test::call_service(
+                    &$app,
+                    test::TestRequest::post()
+                        .uri($path)
+                        .insert_header(("Authorization", format!("Bearer {}", $token)))
+                        .set_json($record)
+                        .to_request(),
+                )
+                .await
+            };
         }
 
         #[macro_export]
@@ -296,8 +1173,8 @@ mod tests {
 
         let resp = query::post!(app,"/register",Register {
             username: "TEST".into(),
-            password1: "PASSWORD".into(),
-            password2: "PASSWORD".into(),
+            password1: "Secret-Phrase-1".into(),
+            password2: "Secret-Phrase-1".into(),
         });
  
         assert!(resp.status().is_success());
@@ -326,8 +1203,8 @@ mod tests {
 
         let resp = query::post!(app,"/register",Register {
             username: "USERNAME".into(), // must be unique
-            password1: "PASSWORD".into(),
-            password2: "PASSWORD".into(),
+            password1: "Secret-Phrase-1".into(),
+            password2: "Secret-Phrase-1".into(),
         });
 
         assert!(!resp.status().is_success());
@@ -335,11 +1212,45 @@ mod tests {
         test_utils::teardown("test_endpoint_register3");
     }
 
+    #[actix_web::test]
+    async fn test_endpoint_register4() {
+        let database = "test_endpoint_register4";
+        let app = test_utils::setup(database).await;
+
+        let form = |username: &str, password: &str| Register {
+            username: username.into(),
+            password1: password.into(),
+            password2: password.into(),
+        };
+
+        // fails because names are unique ignoring case, even when
+        // written with lookalike fullwidth letters
+        let resp = query::post!(app,"/register",form("username", "Secret-Phrase-1"));
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let resp = query::post!(app,"/register",form("ＵＳＥＲＮＡＭＥ", "Secret-Phrase-1"));
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let body: serde_json::Value = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(body["fields"]["username"][0]["code"], "taken");
+
+        // fails because the name is reserved
+        let resp = query::post!(app,"/register",form("Admin", "Secret-Phrase-1"));
+        let body: serde_json::Value = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(body["fields"]["username"][0]["code"], "reserved");
+
+        // fails because the password is too weak
+        let resp = query::post!(app,"/register",form("TEST", "PASSWORD"));
+        let body: serde_json::Value = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(body["fields"]["password1"][0]["code"], "weak_password");
+
+        test_utils::teardown(database);
+    }
+
     #[actix_web::test]
     async fn test_endpoint_login1() {
         let app = test_utils::setup("test_endpoint_login1").await;
 
-        let resp = query::get!(app,"/login",Login {
+        let resp = query::post!(app,"/login",Login {
             username: "USERNAME".into(),
             password: "PASSWORD".into(),
         });
@@ -360,7 +1271,7 @@ mod tests {
         let app = test_utils::setup("test_endpoint_login2").await;
 
         // fails because the username doesn't exist
-        let resp = query::get!(app,"/login",Login {
+        let resp = query::post!(app,"/login",Login {
             username: "BADNAME".into(),
             password: "PASSWORD".into(),
         });
@@ -375,7 +1286,7 @@ mod tests {
         let app = test_utils::setup("test_endpoint_login3").await;
 
         // fails because the password is wrong
-        let resp = query::get!(app,"/login",Login {
+        let resp = query::post!(app,"/login",Login {
             username: "USERNAME".into(),
             password: "BADPASSWORD".into(),
         });
@@ -385,6 +1296,710 @@ mod tests {
         test_utils::teardown("test_endpoint_login3");
     }
 
+    #[actix_web::test]
+    async fn test_endpoint_login4() {
+        let database = "test_endpoint_login4";
+        let app = test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+
+        let character = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+        queries::create_ban(&pool, character.id, "CHEATING", None, None).await.unwrap();
+
+        // fails because the account is banned, and says why
+        let resp = query::post!(app,"/login",Login {
+            username: "USERNAME".into(),
+            password: "PASSWORD".into(),
+        });
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let body: serde_json::Value = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(body["code"], "banned");
+        assert_eq!(body["reason"], "CHEATING");
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_endpoint_profile1() {
+        let database = "test_endpoint_profile1";
+        let app = test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+
+        let character = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+        let resp = query::get!(app,&format!("/characters/{}", character.id),());
+
+        assert!(resp.status().is_success());
+
+        let body = test::read_body(resp).await;
+        let record: Profile = serde_json::from_slice(&body).unwrap();
+
+        // the character has never connected
+        assert_eq!(record.username, "USERNAME");
+        assert!(!record.online);
+        assert_eq!(record.last_seen, None);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_endpoint_profile_by_name() {
+        let database = "test_endpoint_profile_by_name";
+        let app = test_utils::setup(database).await;
+
+        let resp = query::get!(app,"/characters/by-name/USERNAME",());
+        assert!(resp.status().is_success());
+
+        // the password hash is never part of a profile
+        let body = test::read_body(resp).await;
+        assert!(!String::from_utf8_lossy(&body).contains("password"));
+        let record: Profile = serde_json::from_slice(&body).unwrap();
+        assert_eq!(record.username, "USERNAME");
+
+        let resp = query::get!(app,"/characters/by-name/NOBODY",());
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_endpoint_online() {
+        let database = "test_endpoint_online";
+        let app = test_utils::setup(database).await;
+
+        let resp = query::get!(app,"/characters/online?limit=100",());
+        assert!(resp.status().is_success());
+
+        // fails because pages can't be empty
+        let resp = query::get!(app,"/characters/online?limit=0",());
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        // accounts waiting to be deleted don't cut a page short
+        let pool = test_utils::pool(database).await;
+        let leaving = queries::create_character(&pool, "LEAVING", "PASSWORD").await.unwrap();
+        let staying = queries::create_character(&pool, "STAYING", "PASSWORD").await.unwrap();
+        for character in [&leaving, &staying] {
+            register_handler(AccountInfo { id: character.id, username: character.username.clone(), role: Role::Player, session: None, key: None }).await;
+        }
+        let later = chrono::Utc::now() + chrono::Duration::minutes(5);
+        queries::request_deletion(&pool, leaving.id, later).await.unwrap();
+
+        let resp = query::get!(app,&format!("/characters/online?limit=1&after={}", leaving.id - 1),());
+        let page: CharacterPage = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        unregister_handler(leaving.id).await;
+        unregister_handler(staying.id).await;
+        assert_eq!(page.characters.len(), 1);
+        assert_eq!(page.characters[0].username, "STAYING");
+        assert_eq!(page.next, Some(staying.id));
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_endpoint_characters_within() {
+        let database = "test_endpoint_characters_within";
+        let app = test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+
+        let first = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+        let second = queries::create_character(&pool, "NEAR", "PASSWORD").await.unwrap();
+        let third = queries::create_character(&pool, "FAR", "PASSWORD").await.unwrap();
+        queries::update_positions(&pool, vec![
+            (first.id, 1.0, 1.0),
+            (second.id, 2.0, 2.0),
+            (third.id, 50.0, 50.0),
+        ]).await.unwrap();
+
+        let within = |query: &str| test::TestRequest::get()
+            .uri(&format!("/world/characters?{}", query))
+            .to_request();
+
+        let resp = test::call_service(&app, within("x1=10&y1=10&x2=0&y2=0")).await;
+        let page: CharacterPage = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        let names = page.characters.iter().map(|c| c.username.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["USERNAME", "NEAR"]);
+
+        // the corner of the square is outside the circle
+        let resp = test::call_service(&app, within("x=0&y=0&radius=2")).await;
+        let page: CharacterPage = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(page.characters.len(), 1);
+        assert_eq!(page.next, None);
+
+        // pages continue after the last id of the one before
+        let resp = test::call_service(&app, within("x=0&y=0&radius=100&limit=2")).await;
+        let page: CharacterPage = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(page.characters.len(), 2);
+        let resp = test::call_service(&app, within(&format!("x=0&y=0&radius=100&limit=2&after={}", page.next.unwrap()))).await;
+        let page: CharacterPage = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(page.characters[0].username, "FAR");
+
+        // positions in memory are used over the saved ones
+        positions::record(first.id, 50.0, 50.0).await;
+        positions::record(third.id, 3.0, 3.0).await;
+        let resp = test::call_service(&app, within("x1=10&y1=10&x2=0&y2=0")).await;
+        let page: CharacterPage = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        let names = page.characters.iter().map(|c| c.username.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["NEAR", "FAR"]);
+        positions::POSITIONS.lock().await.remove(&first.id);
+        positions::POSITIONS.lock().await.remove(&third.id);
+
+        // accounts waiting to be deleted are hidden
+        let later = chrono::Utc::now() + chrono::Duration::minutes(5);
+        queries::request_deletion(&pool, second.id, later).await.unwrap();
+        let resp = test::call_service(&app, within("x1=10&y1=10&x2=0&y2=0")).await;
+        let page: CharacterPage = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(page.characters.len(), 1);
+        let resp = query::get!(app,&format!("/characters/{}", second.id),());
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let resp = query::get!(app,"/characters/by-name/NEAR",());
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // fails because the shape is incomplete
+        let resp = query::get!(app,"/world/characters?x=0&y=0",());
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_endpoint_chat_history() {
+        let database = "test_endpoint_chat_history";
+        let app = test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+
+        let character = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+        for (channel, text) in [("global", "one"), ("local", "two"), ("global", "three")] {
+            queries::insert_chat(&pool, crate::models::ChatInsert {
+                sender_id: character.id,
+                sender: character.username.clone(),
+                channel: channel.into(),
+                recipient_id: None,
+                party: None,
+                text: text.into(),
+                sent: chrono::Utc::now(),
+            }).await.unwrap();
+        }
+
+        // only global messages are returned, newest first
+        let resp = query::get!(app,"/chat/global?limit=1",());
+        assert!(resp.status().is_success());
+
+        let page: ChatPage = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(page.messages[0].text, "three");
+
+        let resp = query::get!(app,&format!("/chat/global?limit=1&before={}", page.next.unwrap()),());
+        let page: ChatPage = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(page.messages[0].text, "one");
+
+        // fails because the limit is too large
+        let resp = query::get!(app,"/chat/global?limit=1000",());
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_endpoint_report() {
+        let database = "test_endpoint_report";
+        let app = test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+
+        let reporter = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+        let target = queries::create_character(&pool, "TEST", "PASSWORD").await.unwrap();
+        moderation::remember(target.id, "global", "something rude", chrono::Utc::now()).await;
+
+        let token = test_utils::token(&pool, reporter.id, Role::Player).await;
+
+        let resp = query::post!(app,"/report",Report {
+            target: target.id,
+            reason: "RUDE".into()
+        },token);
+        assert!(resp.status().is_success());
+
+        // the target's recent messages are attached to the report
+        let report: crate::models::ReportSelect = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        let (_, messages) = queries::fetch_report(&pool, report.id).await.unwrap();
+        assert_eq!(messages[0].text, "something rude");
+
+        // fails because players can't report themselves
+        let resp = query::post!(app,"/report",Report {
+            target: reporter.id,
+            reason: "RUDE".into()
+        },token);
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // fails because there's no login, with a json error
+        let resp = query::post!(app,"/report",Report {
+            target: target.id,
+            reason: "RUDE".into()
+        });
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let body: serde_json::Value = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(body["code"], "unauthorized");
+
+        // fails because the reporter was banned after logging in
+        queries::create_ban(&pool, reporter.id, "SPAM", None, None).await.unwrap();
+        let resp = query::post!(app,"/report",Report {
+            target: target.id,
+            reason: "RUDE".into()
+        },token);
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        moderation::RECENT.lock().await.remove(&target.id);
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_endpoint_profile2() {
+        let database = "test_endpoint_profile2";
+        let app = test_utils::setup(database).await;
+
+        // fails because the character doesn't exist
+        let resp = query::get!(app,"/characters/0",());
+
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_resume_handler1() {
+        // the token issued to the lingering handler resumes it
+        let token = utilities::random_uuid();
+        LINGERING.lock().await.insert(-1, token);
+
+        assert!(resume_handler(-1, token).await);
+        assert!(!LINGERING.lock().await.contains_key(&-1));
+    }
+
+    #[actix_web::test]
+    async fn test_resume_handler2() {
+        // any other token is rejected and the handler keeps lingering
+        let token = utilities::random_uuid();
+        LINGERING.lock().await.insert(-2, token);
+
+        assert!(!resume_handler(-2, utilities::random_uuid()).await);
+        assert!(LINGERING.lock().await.contains_key(&-2));
+
+        LINGERING.lock().await.remove(&-2);
+    }
+
+    #[actix_web::test]
+    async fn test_resume_handler3() {
+        // a handler that isn't lingering can't be resumed
+        assert!(!resume_handler(-3, utilities::random_uuid()).await);
+    }
+
+    #[actix_web::test]
+    async fn test_endpoint_login5() {
+        let database = "test_endpoint_login5";
+        let app = test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+
+        let character = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+        queries::set_role(&pool, character.id, "admin").await.unwrap();
+
+        // the role is returned and carried in the token
+        let resp = query::post!(app,"/login",Login {
+            username: "USERNAME".into(),
+            password: "PASSWORD".into(),
+        });
+        let account: AccountKey = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(account.role, Role::Admin);
+
+        let info: AccountInfo = utilities::token::decode(&account.token).unwrap();
+        assert_eq!(info.role, Role::Admin);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_endpoint_login6() {
+        let database = "test_endpoint_login6";
+        let app = test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+
+        let attempt = || test::TestRequest::post()
+            .uri("/login")
+            .peer_addr("10.0.0.6:1234".parse().unwrap())
+            .set_json(Login {
+                username: "LOCKED".into(),
+                password: "PASSWORD".into(),
+            })
+            .to_request();
+
+        // unknown usernames fail like wrong passwords, until locked out
+        for _ in 0..CONFIG.login_attempts {
+            let resp = test::call_service(&app, attempt()).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        let resp = test::call_service(&app, attempt()).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let resp = test::call_service(&app, attempt()).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(resp.headers().contains_key("Retry-After"));
+
+        // every failure is audited
+        let attempts = queries::fetch_login_attempts(&pool, "LOCKED").await.unwrap();
+        let reasons = attempts.iter().map(|a| a.reason.as_str()).collect::<Vec<_>>();
+        assert_eq!(reasons.len() as u32, CONFIG.login_attempts + 2);
+        assert_eq!(reasons[0], "unknown_username");
+        assert_eq!(reasons.last(), Some(&"locked"));
+        assert_eq!(attempts[0].address.as_deref(), Some("10.0.0.6"));
+
+        throttle::LOGINS.lock().await.succeed(&Key::Address("10.0.0.6".into()));
+        throttle::LOGINS.lock().await.succeed(&Key::Username("LOCKED".into()));
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_endpoint_login7() {
+        let database = "test_endpoint_login7";
+        let app = test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+
+        // a hash made with a cheaper cost than the configured one
+        let params = argon2::Params::new(argon2::Params::MIN_M_COST, 1, 1, None).unwrap();
+        let old = argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+            .hash_password(b"PASSWORD", &SaltString::generate(&mut OsRng))
+            .unwrap()
+            .to_string();
+
+        let character = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+        queries::update_password(&pool, character.id, old.as_str()).await.unwrap();
+
+        // logging in replaces it with one at the current cost
+        let resp = query::post!(app,"/login",Login {
+            username: "USERNAME".into(),
+            password: "PASSWORD".into(),
+        });
+        assert!(resp.status().is_success());
+
+        let character = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+        assert_ne!(character.password, old);
+        assert!(!utilities::password::outdated(&character.password));
+        assert!(utilities::password::valid(character.password.as_str(), "PASSWORD").is_ok());
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_endpoint_login8() {
+        let database = "test_endpoint_login8";
+        let app = test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+
+        // a name saved before usernames were normalized
+        let hash = utilities::password::hash("PASSWORD").unwrap();
+        queries::create_character(&pool, "ＬＥＧＡＣＹ", hash.as_str()).await.unwrap();
+
+        // succeeds because the name is looked up as it was typed
+        let resp = query::post!(app,"/login",Login {
+            username: "ＬＥＧＡＣＹ".into(),
+            password: "PASSWORD".into(),
+        });
+        assert!(resp.status().is_success());
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_kick_handler() {
+        // only connected accounts can be kicked
+        assert!(!kick_handler(-13, "Banned").await);
+
+        register_handler(AccountInfo { id: -13, username: "KICKED".into(), role: Role::Player, session: None, key: None }).await;
+        assert!(kick_handler(-13, "Banned").await);
+        assert_eq!(KICKS.lock().await.get(&-13).map(String::as_str), Some("Banned"));
+
+        unregister_handler(-13).await;
+        assert!(!KICKS.lock().await.contains_key(&-13));
+    }
+
+    #[actix_web::test]
+    async fn test_close_reason() {
+        assert_eq!(close_reason("Banned".into()), "Banned");
+
+        // long reasons are cut at a character boundary
+        let reason = close_reason(format!("Banned: {}", "é".repeat(100)));
+        assert_eq!(reason.len(), 122);
+        assert!(reason.ends_with('é'));
+    }
+
+    #[actix_web::test]
+    async fn test_claim_connection1() {
+        // a second login kicks the first
+        let first = utilities::random_uuid();
+        let second = utilities::random_uuid();
+
+        assert_eq!(claim_connection(-4, first, false, DuplicateLogin::Kick).await, Some(false));
+        assert_eq!(claim_connection(-4, second, false, DuplicateLogin::Kick).await, Some(true));
+
+        assert!(!current_connection(-4, first).await);
+        assert!(current_connection(-4, second).await);
+
+        CONNECTIONS.lock().await.remove(&-4);
+    }
+
+    #[actix_web::test]
+    async fn test_claim_connection2() {
+        // a second login is rejected
+        let first = utilities::random_uuid();
+        let second = utilities::random_uuid();
+
+        assert_eq!(claim_connection(-5, first, false, DuplicateLogin::Reject).await, Some(false));
+        assert_eq!(claim_connection(-5, second, false, DuplicateLogin::Reject).await, None);
+
+        assert!(current_connection(-5, first).await);
+        assert!(!current_connection(-5, second).await);
+
+        CONNECTIONS.lock().await.remove(&-5);
+    }
+
+    #[actix_web::test]
+    async fn test_claim_connection3() {
+        // a lingering session is taken over even if logins are rejected
+        let first = utilities::random_uuid();
+        let second = utilities::random_uuid();
+
+        assert_eq!(claim_connection(-6, first, false, DuplicateLogin::Reject).await, Some(false));
+        LINGERING.lock().await.insert(-6, utilities::random_uuid());
+
+        assert_eq!(claim_connection(-6, second, false, DuplicateLogin::Reject).await, Some(true));
+        assert!(!LINGERING.lock().await.contains_key(&-6));
+        assert!(current_connection(-6, second).await);
+
+        CONNECTIONS.lock().await.remove(&-6);
+    }
+
+    #[actix_web::test]
+    async fn test_send_event() {
+        // only registered handlers receive events
+        register_handler(AccountInfo { id: -7, username: "TEST".into(), role: Role::Player, session: None, key: None }).await;
+
+        let event = Event::Death { target: -7, killer: -8 };
+        send_event(&[-7, -8], event.clone()).await;
+
+        assert_eq!(read_events(-7).await, vec![event]);
+        assert_eq!(read_events(-7).await, vec![]);
+        assert_eq!(read_events(-8).await, vec![]);
+
+        unregister_handler(-7).await;
+    }
+
+    #[actix_web::test]
+    async fn test_refuse() {
+        let account = AccountInfo { id: -9, username: "TEST".into(), role: Role::Player, session: None, key: None };
+        let code = |frame: protocol::Frame| match refuse(&account, &frame) {
+            Some(Control::Error { code, .. }) => Some(code),
+            _ => None
+        };
+
+        assert_eq!(code(protocol::Frame::Message(Message::Attack(-9, -10))), None);
+
+        // moves and attacks can't be made for another character
+        assert_eq!(code(protocol::Frame::Message(Message::Attack(-10, -9))), Some(ErrorCode::WrongAccount));
+        let spoofed = Message::Move(-10, Default::default(), Default::default());
+        assert_eq!(code(protocol::Frame::Message(spoofed)), Some(ErrorCode::WrongAccount));
+
+        // connects, disconnects and snapshots only come from the server
+        let forged = Message::Initial(-9, vec![]);
+        assert_eq!(code(protocol::Frame::Message(forged)), Some(ErrorCode::ServerOnly));
+
+        // and bots can only do what their key allows
+        let bot = AccountInfo {
+            key: Some(keys::KeyGrant { id: 1, scopes: vec![KeyScope::Connect] }),
+            ..account.clone()
+        };
+        let frame = protocol::Frame::Message(Message::Attack(-9, -10));
+        assert!(matches!(
+            refuse(&bot, &frame),
+            Some(Control::Error { code: ErrorCode::NotPermitted, .. })
+        ));
+    }
+
+    #[actix_web::test]
+    async fn test_connect_with_key() {
+        let database = "test_connect_with_key";
+        let app = test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+
+        let bot = queries::create_character(&pool, "TESTBOT", "PASSWORD").await.unwrap();
+        queries::create_bot(&pool, bot.id, None).await.unwrap();
+        let (_, connector) = keys::issue(&pool, bot.id, "TOOL", &[KeyScope::Connect], None).await.unwrap();
+        let (_, reporter) = keys::issue(&pool, bot.id, "TOOL", &[KeyScope::Report], None).await.unwrap();
+
+        // fails because keys in the path would be written to access logs
+        let resp = query::get!(app, &format!("/connect/{}", connector), ());
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let bearer = |key: &str| test::TestRequest::get()
+            .uri("/connect")
+            .insert_header(("Authorization", format!("Bearer {}", key)))
+            .to_request();
+
+        // the key is accepted from the header, and only fails here
+        // because the request isn't a websocket upgrade
+        let resp = test::call_service(&app, bearer(&connector)).await;
+        assert_ne!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_ne!(resp.status(), StatusCode::FORBIDDEN);
+
+        // fails because the key can't connect
+        let resp = test::call_service(&app, bearer(&reporter)).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_socket_spoofed_attack() {
+        let database = "test_socket_spoofed_attack";
+        test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+
+        let character = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+        let other = queries::create_character(&pool, "TARGET", "PASSWORD").await.unwrap();
+        let token = test_utils::token(&pool, character.id, Role::Player).await;
+
+        let mut srv = actix_test::start(move || {
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .service(connect)
+        });
+
+        let hello = serde_json::to_string(&Control::Hello(Handshake {
+            version: PROTOCOL_VERSION,
+            features: vec![]
+        })).unwrap();
+
+        let mut client = srv.ws_at(&format!("/connect/{}", token)).await.unwrap();
+        client.send(ws::Message::Text(hello.into())).await.unwrap();
+
+        // an attack made as the other character is refused
+        let spoofed = Message::Attack(other.id, character.id);
+        let data = serde_json::to_string(&spoofed).unwrap();
+        client.send(ws::Message::Text(data.into())).await.unwrap();
+
+        let code = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(Ok(frame)) = client.next().await {
+                if let Frame::Text(data) = frame {
+                    if let Ok(Control::Error { code, id, .. }) = serde_json::from_slice(&data) {
+                        assert_eq!(id, Some(spoofed.id()));
+                        return Some(code);
+                    }
+                }
+            }
+            None
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(code, Some(ErrorCode::WrongAccount));
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_socket_legacy_client() {
+        let database = "test_socket_legacy_client";
+        test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+
+        let character = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+        let token = test_utils::token(&pool, character.id, Role::Player).await;
+
+        let mut srv = actix_test::start(move || {
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .service(connect)
+        });
+
+        // legacy clients start moving without saying hello
+        let mut client = srv.ws_at(&format!("/connect/{}", token)).await.unwrap();
+        let moved = Message::Move(character.id, Default::default(), Default::default());
+        let data = serde_json::to_string(&moved).unwrap();
+        client.send(ws::Message::Text(data.into())).await.unwrap();
+
+        // and still get the game state, without a welcome
+        let initial = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(Ok(frame)) = client.next().await {
+                match frame {
+                    Frame::Text(data) => {
+                        assert!(serde_json::from_slice::<Control>(&data).is_err());
+                        if let Ok(Message { value: Value::Initial(_), .. }) = serde_json::from_slice(&data) {
+                            return true;
+                        }
+                    },
+                    Frame::Close(_) => return false,
+                    _ => ()
+                }
+            }
+            false
+        })
+        .await
+        .unwrap();
+
+        assert!(initial);
+
+        test_utils::teardown(database);
+    }
+
+    #[actix_web::test]
+    async fn test_socket_duplicate_login() {
+        let database = "test_socket_duplicate_login";
+        test_utils::setup(database).await;
+        let pool = test_utils::pool(database).await;
+
+        let character = queries::fetch_character(&pool, "USERNAME").await.unwrap();
+        let token = test_utils::token(&pool, character.id, Role::Player).await;
+
+        let mut srv = actix_test::start(move || {
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .service(connect)
+        });
+
+        let path = format!("/connect/{}", token);
+        let hello = serde_json::to_string(&Control::Hello(Handshake {
+            version: PROTOCOL_VERSION,
+            features: vec![FEATURE_RESUME.into()]
+        })).unwrap();
+
+        // wait for the first session to receive its resume token
+        let mut first = srv.ws_at(&path).await.unwrap();
+        first.send(ws::Message::Text(hello.clone().into())).await.unwrap();
+        while let Some(Ok(frame)) = first.next().await {
+            if let Frame::Text(data) = frame {
+                if let Ok(Control::Resume { .. }) = serde_json::from_slice(&data) {
+                    break;
+                }
+            }
+        }
+
+        // the second session kicks the first
+        let mut second = srv.ws_at(&path).await.unwrap();
+        second.send(ws::Message::Text(hello.into())).await.unwrap();
+        let reason = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(Ok(frame)) = first.next().await {
+                if let Frame::Close(reason) = frame {
+                    return reason;
+                }
+            }
+            None
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(reason.code, CloseCode::Policy);
+        assert_eq!(reason.description.unwrap(), "Logged in elsewhere");
+        assert!(registered_handler(character.id).await);
+
+        test_utils::teardown(database);
+    }
+
     // #[actix_web::test]
     // async fn test_socket_connect() {
     //     let url = dotenv::var("DATABASE_URL").unwrap();
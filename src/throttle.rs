@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use actix_web::HttpRequest;
+use futures_util::lock::Mutex;
+use once_cell::sync::Lazy;
+
+use crate::config::CONFIG;
+
+// failed logins for each client address and username
+pub static LOGINS: Lazy<Mutex<Throttle>> = Lazy::new(|| {
+    Mutex::new(Throttle::new(
+        CONFIG.login_attempts,
+        CONFIG.login_lockout,
+        CONFIG.login_lockout_max,
+    ))
+});
+
+// password reset requests for each username
+pub static RESETS: Lazy<Mutex<Throttle>> = Lazy::new(|| {
+    Mutex::new(Throttle::new(
+        CONFIG.reset_requests,
+        CONFIG.reset_lockout,
+        CONFIG.reset_lockout,
+    ))
+});
+
+/// The address of the client that sent a request. Proxy headers are
+/// only used if configured, since otherwise anyone could set them.
+pub fn address(req: &HttpRequest) -> Option<String> {
+    match CONFIG.trust_proxy {
+        true => req.connection_info().realip_remote_addr().map(String::from),
+        false => req.peer_addr().map(|a| a.ip().to_string()),
+    }
+}
+
+/// The keys a login attempt from an address is counted against
+pub fn keys(username: &str, address: &Option<String>) -> Vec<Key> {
+    let mut keys = vec![Key::Username(username.to_string())];
+    keys.extend(address.clone().map(Key::Address));
+    keys
+}
+
+/// How long until a login attempt with any of the keys is allowed
+pub async fn locked(keys: &[Key], now: Instant) -> Option<Duration> {
+    let logins = LOGINS.lock().await;
+    keys.iter().filter_map(|k| logins.locked(k, now)).max()
+}
+
+/// Count a failed login attempt against every key
+pub async fn fail(keys: Vec<Key>, now: Instant) {
+    let mut logins = LOGINS.lock().await;
+    for key in keys {
+        logins.fail(key, now);
+    }
+}
+
+/// What failed logins are counted against. Both are checked so that
+/// one address can't guess many usernames and many addresses can't
+/// guess one username.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    Address(String),
+    Username(String),
+}
+
+#[derive(Clone, Debug)]
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked: Option<Instant>,
+}
+
+/// Counts failures per key, locking a key out for exponentially longer
+/// each time it fails once its free attempts are used up.
+#[derive(Clone, Debug)]
+pub struct Throttle {
+    free: u32,
+    base: Duration,
+    max: Duration,
+    failures: HashMap<Key, Failures>,
+}
+
+impl Throttle {
+    pub fn new(free: u32, base: Duration, max: Duration) -> Self {
+        Self {
+            free,
+            base,
+            max,
+            failures: HashMap::new(),
+        }
+    }
+
+    /// How long a key is still locked out for, if it is
+    pub fn locked(&self, key: &Key, now: Instant) -> Option<Duration> {
+        self.failures
+            .get(key)
+            .and_then(|f| f.locked)
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+
+    /// Record a failure, returning how long the key is now locked out for
+    pub fn fail(&mut self, key: Key, now: Instant) -> Option<Duration> {
+        // failures are forgotten once the longest lockout has passed
+        let max = self.max;
+        if self.failures.len() > 10_000 {
+            self.failures.retain(|_, f| now.saturating_duration_since(f.last) <= max);
+        }
+
+        let failures = self.failures
+            .entry(key)
+            .and_modify(|f| if now.saturating_duration_since(f.last) > max { f.count = 0 })
+            .or_insert(Failures { count: 0, last: now, locked: None });
+
+        failures.count += 1;
+        failures.last = now;
+
+        if failures.count <= self.free {
+            return None;
+        }
+
+        // double the lockout for every failure past the free attempts
+        let doublings = (failures.count - self.free - 1).min(31);
+        let lockout = self.base.saturating_mul(1 << doublings).min(self.max);
+
+        failures.locked = Some(now + lockout);
+        Some(lockout)
+    }
+
+    /// Forget the failures for a key after a successful login
+    pub fn succeed(&mut self, key: &Key) {
+        self.failures.remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle() -> Throttle {
+        Throttle::new(3, Duration::from_secs(1), Duration::from_secs(10))
+    }
+
+    fn key() -> Key {
+        Key::Username("USERNAME".into())
+    }
+
+    #[actix_web::test]
+    async fn test_throttle_free() {
+        // the first few failures aren't locked out
+        let now = Instant::now();
+        let mut throttle = throttle();
+
+        for _ in 0..3 {
+            assert_eq!(throttle.fail(key(), now), None);
+        }
+        assert_eq!(throttle.locked(&key(), now), None);
+    }
+
+    #[actix_web::test]
+    async fn test_throttle_lockout() {
+        // lockouts double up to the maximum
+        let now = Instant::now();
+        let mut throttle = throttle();
+
+        for _ in 0..3 {
+            throttle.fail(key(), now);
+        }
+
+        assert_eq!(throttle.fail(key(), now), Some(Duration::from_secs(1)));
+        assert_eq!(throttle.fail(key(), now), Some(Duration::from_secs(2)));
+        assert_eq!(throttle.fail(key(), now), Some(Duration::from_secs(4)));
+        assert_eq!(throttle.fail(key(), now), Some(Duration::from_secs(8)));
+        assert_eq!(throttle.fail(key(), now), Some(Duration::from_secs(10)));
+
+        let later = now + Duration::from_secs(4);
+        assert_eq!(throttle.locked(&key(), later), Some(Duration::from_secs(6)));
+    }
+
+    #[actix_web::test]
+    async fn test_throttle_reset() {
+        // success and time both clear failures
+        let now = Instant::now();
+        let mut throttle = throttle();
+
+        for _ in 0..4 {
+            throttle.fail(key(), now);
+        }
+        throttle.succeed(&key());
+        assert_eq!(throttle.locked(&key(), now), None);
+
+        for _ in 0..3 {
+            throttle.fail(key(), now);
+        }
+        let later = now + Duration::from_secs(11);
+        assert_eq!(throttle.fail(key(), later), None);
+    }
+}
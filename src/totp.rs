@@ -0,0 +1,251 @@
+use chrono::{DateTime, Utc};
+use sha1::{Digest, Sha1};
+use url::Url;
+
+use crate::config::CONFIG;
+use crate::errors::Result;
+use crate::models::TotpSelect;
+use crate::queries::{self, Database};
+use crate::utilities;
+
+/// How long each code is valid for, in seconds
+pub const PERIOD: i64 = 30;
+
+/// How many digits each code has
+pub const DIGITS: u32 = 6;
+
+/// How many recovery codes are issued at a time
+pub const RECOVERY_CODES: usize = 10;
+
+const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+// ------------------------------------------------
+// Codes
+
+/// Encode bytes as unpadded base32 (RFC 4648), the way authenticator
+/// apps expect secrets to be written.
+pub fn encode(bytes: &[u8]) -> String {
+    let mut result = String::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            result.push(ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+
+    if bits > 0 {
+        result.push(ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    result
+}
+
+/// Decode unpadded base32, ignoring case and padding
+pub fn decode(value: &str) -> Option<Vec<u8>> {
+    let mut result = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for c in value.trim_end_matches('=').bytes() {
+        let index = ALPHABET.iter().position(|a| *a == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | index as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((buffer >> bits) as u8);
+        }
+    }
+    Some(result)
+}
+
+// HMAC (RFC 2104) using SHA-1, which is what authenticator apps use
+fn hmac_sha1(key: &[u8], message: &[u8]) -> [u8; 20] {
+    const BLOCK: usize = 64;
+
+    let mut block = [0u8; BLOCK];
+    if key.len() > BLOCK {
+        block[..20].copy_from_slice(&Sha1::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha1::new();
+    inner.update(block.map(|b| b ^ 0x36));
+    inner.update(message);
+
+    let mut outer = Sha1::new();
+    outer.update(block.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+
+    let mut result = [0u8; 20];
+    result.copy_from_slice(&outer.finalize());
+    result
+}
+
+/// The time step a moment falls in
+pub fn step(now: DateTime<Utc>) -> i64 {
+    now.timestamp().div_euclid(PERIOD)
+}
+
+/// The code for a secret at a time step (RFC 6238)
+pub fn code(secret: &[u8], step: i64) -> u32 {
+    let hash = hmac_sha1(secret, &step.to_be_bytes());
+    let offset = (hash[19] & 0xf) as usize;
+    let value = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    value % 10u32.pow(DIGITS)
+}
+
+/// Check a code against a secret, allowing one step either side for
+/// clock drift. Returns the step it matched, unless that step isn't
+/// after the last one accepted.
+pub fn verify(secret: &[u8], code: &str, now: DateTime<Utc>, last_step: Option<i64>) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let current = step(now);
+
+    (current - 1..=current + 1)
+        .filter(|s| last_step.map(|l| *s > l).unwrap_or(true))
+        .find(|s| self::code(secret, *s) == code)
+}
+
+/// Generate a new random secret, base32 encoded
+pub fn secret() -> String {
+    let mut bytes = [0u8; 20];
+    getrandom::fill(&mut bytes).unwrap();
+    encode(&bytes)
+}
+
+/// The `otpauth://` uri that authenticator apps read from a QR code
+pub fn uri(secret: &str, username: &str) -> String {
+    let issuer = &CONFIG.totp_issuer;
+    let mut uri = Url::parse("otpauth://totp/").unwrap();
+    uri.set_path(&format!("{}:{}", issuer, username));
+    uri.query_pairs_mut()
+        .append_pair("secret", secret)
+        .append_pair("issuer", issuer)
+        .append_pair("algorithm", "SHA1")
+        .append_pair("digits", &DIGITS.to_string())
+        .append_pair("period", &PERIOD.to_string());
+    uri.to_string()
+}
+// ------------------------------------------------
+
+// ------------------------------------------------
+// Recovery codes
+
+// recovery codes are compared without case or separators
+fn normalize(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// Replace the recovery codes of a character, returning the new ones.
+/// Only their hashes are kept, so they can't be shown again.
+pub async fn recovery_codes(database: &Database, character_id: i32) -> Result<Vec<String>> {
+    let mut codes = Vec::new();
+    let mut hashes = Vec::new();
+
+    for _ in 0..RECOVERY_CODES {
+        let mut bytes = [0u8; 5];
+        getrandom::fill(&mut bytes).unwrap();
+        let code = encode(&bytes);
+
+        hashes.push(utilities::password::hash(&code)?);
+        codes.push(format!("{}-{}", &code[..4], &code[4..]));
+    }
+
+    queries::replace_recovery_codes(database, character_id, hashes).await?;
+    Ok(codes)
+}
+// ------------------------------------------------
+
+/// Check a code from an authenticator app, or failing that an unused
+/// recovery code, using it up if it's valid.
+pub async fn check(database: &Database, totp: &TotpSelect, code: &str) -> Result<bool> {
+    let secret = decode(&totp.secret).unwrap_or_default();
+
+    if let Some(step) = verify(&secret, code, Utc::now(), totp.last_step) {
+        return Ok(queries::use_totp_step(database, totp.character_id, step).await? > 0);
+    }
+
+    let code = normalize(code);
+    for recovery in queries::fetch_recovery_codes(database, totp.character_id).await? {
+        if utilities::password::valid(recovery.code_hash.as_str(), code.as_str()).is_ok() {
+            return Ok(queries::use_recovery_code(database, recovery.id).await? > 0);
+        }
+    }
+
+    Ok(false)
+}
+
+/// The two factor setup of a character, if it's been enabled
+pub async fn enabled(database: &Database, character_id: i32) -> Result<Option<TotpSelect>> {
+    Ok(queries::fetch_totp(database, character_id)
+        .await?
+        .filter(|t| t.enabled.is_some()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    // the sha1 secret from the test vectors in RFC 6238
+    const SECRET: &[u8] = b"12345678901234567890";
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(seconds, 0).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_base32() {
+        assert_eq!(encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(decode("mzxw6ytboi======"), Some(b"foobar".to_vec()));
+        assert_eq!(decode("not base32!"), None);
+    }
+
+    #[actix_web::test]
+    async fn test_code() {
+        // the last six digits of the RFC 6238 values
+        assert_eq!(code(SECRET, step(at(59))), 287082);
+        assert_eq!(code(SECRET, step(at(1111111109))), 81804);
+        assert_eq!(code(SECRET, step(at(2000000000))), 279037);
+    }
+
+    #[actix_web::test]
+    async fn test_verify() {
+        let now = at(1111111109);
+
+        // codes from the step before and after are accepted
+        assert!(verify(SECRET, "081804", now, None).is_some());
+        assert!(verify(SECRET, "081804", now + chrono::Duration::seconds(30), None).is_some());
+        assert!(verify(SECRET, "081804", now + chrono::Duration::seconds(90), None).is_none());
+
+        // but not once that step has been used
+        let used = verify(SECRET, "081804", now, None);
+        assert!(verify(SECRET, "081804", now, used).is_none());
+
+        assert!(verify(SECRET, "81804", now, None).is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_uri() {
+        let uri = uri("MZXW6YTBOI", "USERNAME");
+        assert!(uri.starts_with("otpauth://totp/"));
+        assert!(uri.contains(":USERNAME?secret=MZXW6YTBOI&issuer="));
+    }
+}
@@ -1,7 +1,11 @@
 use tokio::signal::unix::{signal, SignalKind};
 use tokio::task;
+use tokio::time::sleep;
 
 use tinker_records::messages::{Message,Value};
+use crate::combat;
+use crate::config::CONFIG;
+use crate::positions;
 use crate::queries::{self, Database};
 use crate::routes::{INCOMING_QUEUE,OUTGOING_QUEUE,DATABASE_QUEUE,all_viewed};
 
@@ -33,24 +37,38 @@ async fn process_message(message: Message) {
 async fn insert_message(database: &Database, message: Message) {
     match message.value {
         Value::Move(m) => {
-            queries::update_entity(
-                database, 
+            // positions are written in batches by the flusher
+            positions::record(
                 message.header.account_id, 
                 m.current.x, 
                 m.current.y
             ).await;
         },
         Value::Attack(m) => {
-            
+            combat::attack(
+                database,
+                message.header.account_id,
+                m.target
+            ).await;
         },
         Value::Initial(m) => {
             
         },
-        Value::Connect(m) => {
-            
+        Value::Connect(_) => {
+            let _ = queries::start_session(
+                database,
+                message.header.account_id
+            ).await;
         },
-        Value::Disconnect(m) => {
-            
+        Value::Disconnect(_) => {
+            positions::flush_character(
+                database,
+                message.header.account_id
+            ).await;
+            let _ = queries::end_session(
+                database,
+                message.header.account_id
+            ).await;
         },
     }
 
@@ -76,7 +94,7 @@ async fn insert_message(database: &Database, message: Message) {
 }
 
 pub fn process_messages(pool: Database) {
-    actix_web::rt::spawn(async {
+    actix_web::rt::spawn(async move {
 
         let canceled_task = tokio::signal::ctrl_c();
     
@@ -96,11 +114,33 @@ pub fn process_messages(pool: Database) {
             }
         };
 
+        let database = pool.clone();
         let inserter_task = async move {
             loop {
                 task::yield_now().await;
                 if let Some(message) = DATABASE_QUEUE.lock().await.pop_front() {
-                    insert_message(&pool,message).await;
+                    insert_message(&database,message).await;
+                }
+            }
+        };
+
+        let database = pool.clone();
+        let flusher_task = async move {
+            loop {
+                sleep(CONFIG.flush_interval).await;
+                positions::flush(&database).await;
+            }
+        };
+
+        let database = pool.clone();
+        let purge_task = async move {
+            loop {
+                sleep(CONFIG.purge_interval).await;
+                match queries::purge_deleted(&database).await {
+                    Ok(purged) => for id in purged {
+                        println!("{} PURGED", id);
+                    },
+                    Err(error) => println!("PURGE FAILED: {}", error)
                 }
             }
         };
@@ -117,11 +157,20 @@ pub fn process_messages(pool: Database) {
             _ = terminate_task => println!("Received SIGTERM"),
             _ = processer_task => (),
             _ = inserter_task => (),
+            _ = flusher_task => (),
+            _ = purge_task => (),
             _ = cleanup_task => ()
         };
     });
 }
 
+// generate a fully random uuid for use as a secret token
+pub fn random_uuid() -> uuid::Uuid {
+    let mut bytes = [0u8; 16];
+    getrandom::fill(&mut bytes).unwrap();
+    uuid::Builder::from_random_bytes(bytes).into_uuid()
+}
+
 pub mod token {
     use branca::Branca;
     use once_cell::sync::Lazy;
@@ -149,14 +198,32 @@ pub mod token {
 }
 
 pub mod password {
+    use crate::config::CONFIG;
     use crate::errors::Result;
     use argon2::{
         password_hash::{rand_core::OsRng, SaltString},
-        Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+        Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
     };
+    use once_cell::sync::Lazy;
+
+    // the hash of a password nobody knows, checked when a username
+    // doesn't exist so that it takes as long as a wrong password.
+    static DUMMY: Lazy<String> = Lazy::new(|| {
+        hash(super::random_uuid()).unwrap()
+    });
+
+    // the cost new passwords are hashed with, which is checked when
+    // the config is loaded
+    fn params() -> Params {
+        CONFIG.argon2_params().expect("invalid ARGON2 settings")
+    }
+
+    fn argon2() -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params())
+    }
 
     pub fn hash<T: ToString>(value: T) -> Result<String> {
-        Ok(Argon2::default()
+        Ok(argon2()
             .hash_password(
                 value.to_string().as_bytes(),
                 &SaltString::generate(&mut OsRng),
@@ -165,9 +232,56 @@ pub mod password {
     }
     
     pub fn valid<T: ToString>(value: T, password: T) -> Result<()> {
+        // the cost is read from the hash, so old hashes still verify
         Ok(Argon2::default().verify_password(
             password.to_string().as_bytes(),
             &PasswordHash::new(&value.to_string().as_ref())?,
         )?)
     }
+
+    /// Check if a hash was made with a different algorithm or cost than
+    /// new passwords are, and should be replaced the next time the
+    /// password is known.
+    pub fn outdated<T: AsRef<str>>(value: T) -> bool {
+        let Ok(hash) = PasswordHash::new(value.as_ref()) else {
+            return true;
+        };
+        let Ok(used) = Params::try_from(&hash) else {
+            return true;
+        };
+        let current = params();
+
+        hash.algorithm != Algorithm::Argon2id.ident()
+            || hash.version != Some(Version::V0x13.into())
+            || used.m_cost() != current.m_cost()
+            || used.t_cost() != current.t_cost()
+            || used.p_cost() != current.p_cost()
+    }
+
+    /// Verify a password against a hash that never matches
+    pub fn dummy<T: ToString>(password: T) {
+        let _ = valid(DUMMY.clone(), password.to_string());
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[actix_web::test]
+        async fn test_outdated() {
+            // hashes made with the current cost are kept
+            assert!(!outdated(hash("PASSWORD").unwrap()));
+
+            // hashes made with another cost still verify, but are replaced
+            let params = Params::new(Params::MIN_M_COST, 1, 1, None).unwrap();
+            let old = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                .hash_password(b"PASSWORD", &SaltString::generate(&mut OsRng))
+                .unwrap()
+                .to_string();
+            assert!(valid(old.as_str(), "PASSWORD").is_ok());
+            assert!(outdated(&old));
+
+            assert!(outdated("nonsense"));
+        }
+    }
 }
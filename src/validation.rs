@@ -0,0 +1,215 @@
+use std::borrow::Cow;
+
+use icu_normalizer::ComposingNormalizerBorrowed;
+use validator::{ValidationError, ValidationErrors};
+
+use crate::config::CONFIG;
+use crate::payloads::Area;
+use crate::positions::Shape;
+
+// passwords that are guessed first, whatever their length
+const COMMON: &[&str] = &[
+    "password", "password1", "password123", "passw0rd", "123456789", "1234567890",
+    "12345678", "qwertyuiop", "qwerty123", "iloveyou", "sunshine", "princess",
+    "football", "baseball", "welcome1", "letmein1", "trustno1", "dragon123",
+    "monkey123", "abc12345", "11111111", "00000000", "changeme",
+];
+
+fn error(code: &'static str, message: &'static str) -> ValidationError {
+    ValidationError::new(code).with_message(Cow::Borrowed(message))
+}
+
+/// Normalize a username (NFKC) and trim it, so that names which look
+/// the same are stored the same way.
+pub fn normalize(username: &str) -> String {
+    ComposingNormalizerBorrowed::new_nfkc()
+        .normalize(username)
+        .trim()
+        .to_string()
+}
+
+/// Check that a (normalized) username only uses letters, digits, `_`
+/// and `-` from ASCII, which rules out control characters and lookalike
+/// letters from other scripts, and that it isn't reserved or filtered.
+pub fn username(value: &str) -> Result<(), ValidationError> {
+    if !value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err(error(
+            "invalid_characters",
+            "Usernames can only contain letters, digits, '_' and '-'",
+        ));
+    }
+
+    if CONFIG.reserved_names.matches(value) {
+        return Err(error("reserved", "This username is reserved"));
+    }
+
+    if CONFIG.chat_filter.apply(value) != value {
+        return Err(error("filtered", "This username is not allowed"));
+    }
+
+    Ok(())
+}
+
+/// Score a password from 0 (trivially guessed) to 4 (strong), going by
+/// its length and how many kinds of character it uses.
+pub fn strength(password: &str, username: &str) -> u32 {
+    let lower = password.to_lowercase();
+    let length = password.chars().count();
+
+    // passwords made from the username or a common password score nothing
+    if COMMON.contains(&lower.as_str())
+        || (!username.is_empty() && lower.contains(&username.to_lowercase()))
+    {
+        return 0;
+    }
+
+    // neither does a single repeated character
+    let mut chars = password.chars();
+    if let Some(first) = chars.next() {
+        if chars.all(|c| c == first) {
+            return 0;
+        }
+    }
+
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_numeric()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ]
+    .into_iter()
+    .filter(|c| *c)
+    .count();
+
+    let mut score = 0;
+    if length >= 8 {
+        score += 1;
+    }
+    if length >= 12 {
+        score += 1;
+    }
+    if length >= 16 {
+        score += 1;
+    }
+    if classes >= 3 {
+        score += 1;
+    }
+    score.min(4)
+}
+
+/// Check that a new password is strong enough, reporting it as an error
+/// on the given field.
+pub fn password(field: &'static str, password: &str, username: &str) -> Result<(), ValidationErrors> {
+    if strength(password, username) >= CONFIG.password_strength {
+        return Ok(());
+    }
+
+    let mut errors = ValidationErrors::new();
+    errors.add(field, error(
+        "weak_password",
+        "Use a longer password, or mix cases, digits and symbols",
+    ));
+    Err(errors)
+}
+
+/// Check that an area query describes one shape, with finite numbers
+/// and a radius that isn't negative
+pub fn area(value: &Area) -> Result<(), ValidationError> {
+    let valid = match value.shape() {
+        Some(Shape::Rectangle { min_x, min_y, max_x, max_y }) => {
+            [min_x, min_y, max_x, max_y].iter().all(|v| v.is_finite())
+        }
+        Some(Shape::Circle { x, y, radius }) => {
+            [x, y, radius].iter().all(|v| v.is_finite()) && radius >= 0.0
+        }
+        None => false,
+    };
+
+    match valid {
+        true => Ok(()),
+        false => Err(error(
+            "invalid_area",
+            "Give either x1, y1, x2 and y2, or x, y and radius",
+        )),
+    }
+}
+
+/// The most bytes a websocket close reason can hold
+pub const CLOSE_REASON: usize = 123;
+
+/// Check that a reason fits in a websocket close frame, which is
+/// limited in bytes rather than characters
+pub fn close_reason(value: &str) -> Result<(), ValidationError> {
+    match value.len() <= CLOSE_REASON {
+        true => Ok(()),
+        false => Err(error("too_long", "Reasons can't be longer than 123 bytes")),
+    }
+}
+
+/// The error for a username that is already taken
+pub fn taken() -> ValidationErrors {
+    let mut errors = ValidationErrors::new();
+    errors.add("username", error("taken", "This username is already taken"));
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_normalize() {
+        // fullwidth letters are the same as their ascii forms
+        assert_eq!(normalize(" ＵＳＥＲ "), "USER");
+    }
+
+    #[actix_web::test]
+    async fn test_username() {
+        assert!(username("Some_User-1").is_ok());
+
+        // control characters and lookalike letters are refused
+        assert!(username("USER\u{0007}").is_err());
+        assert!(username("АDMIN").is_err());
+
+        // as are reserved names, whatever their case
+        let reserved = username("Admin").unwrap_err();
+        assert_eq!(reserved.code, "reserved");
+    }
+
+    #[actix_web::test]
+    async fn test_strength() {
+        assert_eq!(strength("password", ""), 0);
+        assert_eq!(strength("aaaaaaaaaaaaaaaa", ""), 0);
+        assert_eq!(strength("USERNAME99", "username"), 0);
+        assert_eq!(strength("lowercase", ""), 1);
+        assert_eq!(strength("Mixed-Case-1", ""), 3);
+        assert_eq!(strength("correct horse battery staple", ""), 3);
+        assert_eq!(strength("Correct-Horse-Battery-9", ""), 4);
+    }
+
+    #[actix_web::test]
+    async fn test_area() {
+        let circle = Area { x: Some(1.0), y: Some(2.0), radius: Some(3.0), ..Default::default() };
+        assert!(area(&circle).is_ok());
+
+        // partial, mixed and negative shapes are refused
+        assert!(area(&Area { x: Some(1.0), ..Default::default() }).is_err());
+        assert!(area(&Area { x1: Some(0.0), ..circle.clone() }).is_err());
+        assert!(area(&Area { radius: Some(-1.0), ..circle }).is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_close_reason() {
+        assert!(close_reason(&"a".repeat(123)).is_ok());
+
+        // multibyte characters count for each of their bytes
+        assert!(close_reason(&"é".repeat(62)).is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_password() {
+        let errors = password("password1", "lowercase", "").unwrap_err();
+        assert!(errors.field_errors().contains_key("password1"));
+        assert!(password("password1", "Mixed-Case-1", "").is_ok());
+    }
+}